@@ -0,0 +1,149 @@
+//! Crash-safe saving and rolling backups of character files.
+//!
+//! Saves never write to the character file directly. They write a temp file next to it and
+//! rename it into place, so a crash leaves either the old file or the new one, never half of
+//! each. Before each save from the server, the old file is copied to
+//! `<file>.<timestamp>.bak` in the same directory, and only the newest few are kept.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_KEEP: usize = 10;
+
+/// A backup of a character file, as found next to it.
+#[derive(Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// UTC, like `20210301T174502Z`.
+    pub timestamp: String
+}
+
+impl Backup {
+    /// The timestamp in a friendlier format, like `2021-03-01 17:45:02 UTC`.
+    pub fn time(&self) -> String {
+        let t = &self.timestamp;
+        if t.len() != 16 {
+            return t.clone();
+        }
+        format!("{}-{}-{} {}:{}:{} UTC", &t[0..4], &t[4..6], &t[6..8], &t[9..11], &t[11..13], &t[13..15])
+    }
+}
+
+/// Replaces the contents of `path` with `contents`, all or nothing.
+pub fn write_atomic(path: &str, contents: &str) -> io::Result<()> {
+    let temp = format!("{}.tmp", path);
+    let mut file = std::fs::File::create(&temp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::mem::drop(file);
+    std::fs::rename(&temp, path)
+}
+
+/// Copies the current contents of `path` to a new backup, then deletes all but the newest `keep`.
+///
+/// Does nothing if `keep` is 0 or the file doesn't exist yet. Backups made within the same
+/// second replace each other.
+pub fn back_up(path: &str, keep: usize) -> io::Result<()> {
+    if keep == 0 || !Path::new(path).exists() {
+        return Ok(());
+    }
+    let contents = std::fs::read_to_string(path)?;
+    write_atomic(&format!("{}.{}.bak", path, timestamp(SystemTime::now())), &contents)?;
+    for old in list(path)?.into_iter().skip(keep) {
+        std::fs::remove_file(old.path)?;
+    }
+    Ok(())
+}
+
+/// All backups of `path`, newest first.
+pub fn list(path: &str) -> io::Result<Vec<Backup>> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new(".")
+    };
+    let prefix = format!("{}.", path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default());
+    let mut backups = vec! [];
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(timestamp) = name.strip_prefix(&prefix).and_then(|rest| rest.strip_suffix(".bak")) {
+            backups.push(Backup {
+                path: dir.join(&name),
+                timestamp: timestamp.to_string()
+            });
+        }
+    }
+    backups.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    Ok(backups)
+}
+
+/// Puts a backup's contents back into `path`. The current contents are backed up first, so
+/// this can be undone.
+pub fn restore(path: &str, backup: &Backup, keep: usize) -> io::Result<()> {
+    let contents = std::fs::read_to_string(&backup.path)?;
+    back_up(path, keep.max(1))?;
+    write_atomic(path, &contents)
+}
+
+/// Formats a time as a UTC timestamp that sorts correctly as a string, like `20210301T174502Z`.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Days since the epoch to a date in the proleptic Gregorian calendar.
+    // From http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamps() {
+        let at = |secs| timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "19700101T000000Z");
+        assert_eq!(at(951_782_400), "20000229T000000Z");
+        assert_eq!(at(1_614_620_702), "20210301T174502Z");
+    }
+
+    #[test]
+    fn backups_roll_and_restore() {
+        let dir = std::env::temp_dir().join(format!("dndcent-backup-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("character.json");
+        let path = path.to_str().unwrap();
+
+        write_atomic(path, "one").unwrap();
+        // Backups within the same second replace each other, so fake some older ones.
+        for t in &["20000101T000000Z", "20000102T000000Z", "20000103T000000Z"] {
+            std::fs::write(format!("{}.{}.bak", path, t), t).unwrap();
+        }
+        back_up(path, 3).unwrap();
+        write_atomic(path, "two").unwrap();
+
+        let backups = list(path).unwrap();
+        let timestamps: Vec<&str> = backups.iter().map(|b| &*b.timestamp).collect();
+        assert_eq!(timestamps.len(), 3);
+        assert_eq!(&timestamps[1..], &["20000103T000000Z", "20000102T000000Z"]);
+        assert_eq!(std::fs::read_to_string(&backups[0].path).unwrap(), "one");
+
+        restore(path, &backups[0], 3).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "one");
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,14 +2,22 @@ use serde::{Deserialize, Serialize};
 
 use crate::ui::{Element, Event};
 use crate::misc::*;
-use std::fmt::Debug;
+use crate::money::Transaction;
+use std::fmt::{Debug, Display, Formatter};
+use std::cell::RefCell;
 use proc_macros::FinalizeCharacter;
 use crate::content::common::{common_rules, common_race_rules, common_class_rules, common_item_rules, common_background_rules};
 use crate::content::traits::{Race, Class, Item, Background};
 use std::ops::{Deref, DerefMut};
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet, BTreeMap};
+use serde_json::Value;
 use maplit::hashmap;
 use crate::moves::*;
+use crate::graph::DependencyGraph;
+use crate::migration;
+use crate::unknown;
+use crate::backup;
+use colored::Colorize;
 
 /// Version of the Character struct that is stored as a json file for saving.
 ///
@@ -19,6 +27,9 @@ use crate::moves::*;
 /// of the nested content information is handled automatically by Serde and Typetag.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct StoredCharacter {
+    /// See [migration](crate::migration) for how older files are upgraded.
+    schema_version: u64,
+
     pub(crate) name: String,
 
     pub(crate) health: u32,
@@ -30,34 +41,88 @@ pub struct StoredCharacter {
 
     inspiration: bool,
 
-    money: MoneyTypeMap<u32>,
+    /// Whether the variant encumbrance rule is used, see [Encumbrance].
+    pub(crate) variant_encumbrance: bool,
+
+    pub(crate) money: MoneyTypeMap<u32>,
+    /// Every change to `money` made through an edit, oldest first.
+    pub(crate) ledger: Vec<Transaction>,
 
     pub(crate) race: Box<dyn Race>,
     pub(crate) classes: Vec<(Box<dyn Class>, u32)>,
     pub(crate) background: Box<dyn Background>,
 
-    inventory: Vec<(Box<dyn Item>, Equipped, bool)>,
+    pub(crate) inventory: Vec<(Box<dyn Item>, Equipped, bool)>,
 
     pub(crate) description: String
 }
 
 impl StoredCharacter {
     pub fn read(path: &str) -> StoredCharacter {
-        let json = std::fs::read_to_string(path).expect(&format!("READING FAILED: {}", path));
-        serde_json::from_str(&json).expect("DESERIALIZATION FAILED")
+        let json = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("READING FAILED: {}", path));
+        let json = serde_json::from_str(&json).expect("DESERIALIZATION FAILED");
+        let (character, replaced) = StoredCharacter::from_json(json)
+            .unwrap_or_else(|e| panic!("LOADING FAILED: {}: {}", path, e));
+        for r in replaced {
+            eprintln!("{}", format!("WARNING: {}: {}", path, r).yellow());
+        }
+        character
     }
-    pub fn write(&self, path: &str) {
-        let json = serde_json::to_string_pretty(&self).expect("SERIALIZATION FAILED");
-        std::fs::write(path, json).expect(&format!("WRITING FAILED: {}", path));
+
+    /// Migrates and deserializes a character document, loading unknown content as placeholders.
+    pub fn from_json(json: serde_json::Value) -> Result<(StoredCharacter, Vec<unknown::Replaced>), String> {
+        let json = migration::migrate(json).map_err(|e| e.to_string())?;
+        unknown::deserialize(json).map_err(|e| e.to_string())
+    }
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let mut json = serde_json::to_value(&self).expect("SERIALIZATION FAILED");
+        unknown::restore(&mut json);
+        let json = serde_json::to_string_pretty(&json).expect("SERIALIZATION FAILED");
+        backup::write_atomic(path, &json)
     }
 
     /// This performs all of the logic of expanding the stored character into a full character.
     ///
     /// 1. Copy/clone some quantities from the stored character directly into the character struct. (like health and alignment)
-    /// 2. Repeatedly call resolve on all content attached to the character. The Staged objects,
+    /// 2. Call resolve on all content attached to the character. The Staged objects,
     ///    combined with the i!, m!, and f! macros (see proc_macros/src/content.rs) will self-organize
-    ///    the dependencies.
-    pub fn resolve(&mut self) -> Result<FinalCharacter, ()> {
+    ///    the dependencies. See [ResolutionStrategy] for how.
+    /// 3. If anything is still pending at the end, give up and return a [DeadlockReport] of
+    ///    everything still pending.
+    pub fn resolve(&mut self) -> Result<FinalCharacter, DeadlockReport> {
+        self.resolve_with(ResolutionStrategy::default())
+    }
+
+    pub fn resolve_with(&mut self, strategy: ResolutionStrategy) -> Result<FinalCharacter, DeadlockReport> {
+        let mut char = self.new_character();
+        self.expand(&mut char, strategy);
+        if char.count_unresolved() != 0 {
+            Err(char.deadlock_report())
+        } else {
+            Ok(char.finalize())
+        }
+    }
+
+    /// Runs all of the content once, without finishing the character or reporting a deadlock.
+    /// This is how [edit_element](crate::ui::edit_element) and [rest](crate::ui::rest) get to
+    /// the content's fields.
+    pub fn run_content(&mut self) {
+        let mut char = self.new_character();
+        self.expand(&mut char, ResolutionStrategy::default());
+    }
+
+    /// Resolves the character as far as it will go, and returns the graph of which stages read
+    /// and wrote which fields along the way.
+    ///
+    /// This works on deadlocked characters too; the stages that never ran show what they were
+    /// waiting on.
+    pub fn dependency_graph(&mut self) -> DependencyGraph {
+        let mut char = self.new_character();
+        self.expand(&mut char, ResolutionStrategy::default());
+        DependencyGraph::from_character(&char)
+    }
+
+    fn new_character(&self) -> Character {
         let mut char = Character {
             health: Staged::new(self.health),
             temp_health: Staged::new(self.temp_health),
@@ -70,53 +135,96 @@ impl StoredCharacter {
             description: Staged::new(self.description.clone()),
 
             money: self.money.wrap_staged(),
+            ledger: Staged::new(self.ledger.clone()),
 
             inspiration: Staged::new(self.inspiration),
 
+            variant_encumbrance: Staged::new(self.variant_encumbrance),
+
             alignment: Staged::new(self.alignment),
 
+            inventory: Staged::new(self.inventory.iter().map(|(item, equipped, attuned)| InventoryItem {
+                name: item.name(),
+                equipped: *equipped,
+                attuned: *attuned,
+                equipable: item.equipable(),
+                attunable: item.attunable()
+            }).collect()),
+            warnings: Staged::new(common_item_rules::check(&self.inventory).iter().map(ToString::to_string).collect()),
+
             ..Default::default()
         };
 
         for _ in 0..self.classes.len() {
             char.class_features.push(Staged::new(vec![]));
         }
+        char
+    }
+
+    /// Runs the content until everything is resolved, or it can't go any further.
+    ///
+    /// Takes the character by reference because the stages remember the addresses of the
+    /// fields they read, so it must not move until the caller is done with it.
+    fn expand(&mut self, char: &mut Character, strategy: ResolutionStrategy) {
+        match strategy {
+            ResolutionStrategy::Topological => {
+                with_schedule(Schedule::Discover, || self.resolve_content(char));
+                for level in char.stage_levels() {
+                    with_schedule(Schedule::Run(level), || self.resolve_content(char));
+                }
+                // Reads are only known once a stage registers, so a stage that wasn't ready in
+                // its level, or that first showed up in one of those passes, gets another go.
+                if char.count_unresolved() != 0 {
+                    self.fixed_point(char);
+                }
+            }
+            ResolutionStrategy::FixedPoint => self.fixed_point(char)
+        }
+    }
 
+    /// Runs all of the content over and over until a pass makes no progress.
+    fn fixed_point(&mut self, char: &mut Character) {
         let mut old_count: i64  = -2;
         let mut count: i64 = -1;
-        // let mut iterations = 0;
         while count != 0 && old_count != count {
             old_count = count;
-
-            common_rules::resolve(&mut char);
-            common_race_rules::resolve(&mut char, &self.race);
-            self.race.resolve(&mut char);
-            for (i, (class, level)) in self.classes.iter_mut().enumerate() {
-                common_class_rules::resolve(&mut char, class, *level, i);
-                class.resolve(&mut char, *level, i);
-            }
-            common_background_rules::resolve(&mut char, &self.background);
-            self.background.resolve(&mut char);
-            for (item, equipped, attuned) in &mut self.inventory {
-                common_item_rules::resolve(&mut char, item, *equipped, *attuned);
-                item.resolve(&mut char, *equipped, *attuned);
-            }
-
+            with_schedule(Schedule::FixedPoint, || self.resolve_content(char));
             count = char.count_unresolved().into();
-
-            // iterations += 1;
         }
-        // dbg!(iterations);
-        if count != 0 {
-            dbg!(&char);
-            println!("RESOLUTION DEADLOCK");
-            Err(())
-        } else {
+    }
 
-            Ok(char.finalize())
+    /// Calls resolve once on every piece of content, in a fixed order.
+    fn resolve_content(&mut self, char: &mut Character) {
+        with_source(Source::CommonRules, || common_rules::resolve(char));
+        with_source(Source::Race(self.race.name()), || {
+            common_race_rules::resolve(char, &*self.race);
+            self.race.resolve(char);
+        });
+        for (i, (class, level)) in self.classes.iter_mut().enumerate() {
+            with_source(Source::Class { index: i, name: class.name() }, || {
+                common_class_rules::resolve(char, &**class, *level, i);
+                class.resolve(char, *level, i);
+            });
+        }
+        with_source(Source::Background(self.background.name()), || {
+            common_background_rules::resolve(char, &*self.background);
+            self.background.resolve(char);
+        });
+        let mut seen: HashMap<&'static str, usize> = HashMap::new();
+        for (i, (item, equipped, attuned)) in self.inventory.iter_mut().enumerate() {
+            let name = item.name();
+            let occurrence = seen.entry(name).or_insert(0);
+            let source = Source::Item { index: i, name, occurrence: *occurrence };
+            *occurrence += 1;
+            with_source(source, || {
+                common_item_rules::resolve(char, &**item, *equipped, *attuned, i);
+                item.resolve(char, *equipped, *attuned);
+            });
         }
     }
 
+    /// Passes `e` to all of the content. Rests also recharge every [Element::Resource] that comes
+    /// back on them.
     pub fn event(&mut self, e: Event) {
         self.race.event(&e);
         for (i, (class, level)) in self.classes.iter_mut().enumerate() {
@@ -126,12 +234,37 @@ impl StoredCharacter {
         for (item, equipped, attuned) in &mut self.inventory {
             item.event(&e, *equipped, *attuned);
         }
+        crate::ui::rest(&e, || self.run_content());
+    }
+}
+
+/// How [StoredCharacter::resolve_with] orders the stages.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResolutionStrategy {
+    /// Call all the content once to register every stage, then sort the stages by the fields
+    /// they read and write. After that, each pass over the content runs only the stages whose
+    /// dependencies finished in earlier passes, so every stage runs exactly once. Anything
+    /// still pending afterward is left to [FixedPoint](ResolutionStrategy::FixedPoint).
+    Topological,
+
+    /// The original approach. Call all the content over and over, running whatever stages
+    /// happen to be ready, until a pass makes no progress.
+    ///
+    /// Kept around so the tests can check that both strategies agree.
+    #[cfg_attr(not(test), allow(dead_code))]
+    FixedPoint
+}
+
+impl Default for ResolutionStrategy {
+    fn default() -> Self {
+        ResolutionStrategy::Topological
     }
 }
 
 impl Default for StoredCharacter {
     fn default() -> Self {
         StoredCharacter {
+            schema_version: migration::CURRENT_VERSION,
             name: "".to_string(),
             health: 0,
             temp_health: 0,
@@ -145,6 +278,7 @@ impl Default for StoredCharacter {
             },
             alignment: Alignment::Unknown,
             inspiration: false,
+            variant_encumbrance: false,
             money: MoneyTypeMap {
                 platinum: 0,
                 gold: 0,
@@ -152,6 +286,7 @@ impl Default for StoredCharacter {
                 silver: 0,
                 copper: 0,
             },
+            ledger: vec![],
             race: crate::content::default_race(),
             background: crate::content::default_background(),
             classes: vec![],
@@ -187,6 +322,9 @@ pub struct Character {
 
     // ARMOR
     pub armor_class: Staged<u32>,
+    /// Armor classes to use instead of 10 + Dex when no armor is worn, like a Barbarian's
+    /// Unarmored Defense. The highest one wins.
+    pub unarmored_defenses: Staged<Vec<u32>>,
 
     // ABILITIES
     pub abilities: AbilityMap<Staged<u32>>,
@@ -233,6 +371,7 @@ pub struct Character {
     pub race_choices: Staged<Vec<&'static str>>,
     pub class_choices: Staged<Vec<&'static str>>,
     pub background_choices: Staged<Vec<&'static str>>,
+    pub item_choices: Staged<Vec<&'static str>>,
 
     pub race_traits: Staged<Vec<Element<'static>>>,
     pub class_features: Vec<Staged<Vec<Element<'static>>>>,
@@ -246,12 +385,28 @@ pub struct Character {
     pub both_hands: Staged<Option<&'static str>>,
     pub ammunition: Staged<Option<&'static str>>,
     pub armor: Staged<Option<&'static str>>,
+    pub worn_armor: Staged<Option<Armor>>,
+
+    pub inventory: Staged<Vec<InventoryItem>>,
+    /// Rules the inventory breaks, see [check](common_item_rules::check).
+    pub warnings: Staged<Vec<String>>,
 
     pub hold_choices: Staged<Vec<&'static str>>,
     pub armor_choices: Staged<Vec<&'static str>>,
     pub ammunition_choices: Staged<Vec<&'static str>>,
 
     pub money: MoneyTypeMap<Staged<u32>>,
+    pub ledger: Staged<Vec<Transaction>>,
+
+    /// In pounds, counting every item in the inventory and 50 coins to the pound.
+    pub carried_weight: Staged<u32>,
+    pub carrying_capacity: Staged<u32>,
+    pub push_drag_lift: Staged<u32>,
+    pub variant_encumbrance: Staged<bool>,
+    /// The weights past which the character is encumbered and heavily encumbered, only with
+    /// the variant encumbrance rule.
+    pub encumbrance_thresholds: Staged<Option<(u32, u32)>>,
+    pub encumbrance: Staged<Encumbrance>,
 
     pub inspiration: Staged<bool>,
 
@@ -262,20 +417,477 @@ pub struct Character {
     pub base_abilities: AbilityMap<Staged<u32>>
 }
 
+impl Character {
+    /// Groups every registered stage by how long its longest chain of dependencies is.
+    ///
+    /// A stage depends on every stage of every field it reads, and on the earlier stages of
+    /// the field it writes. Stages caught in a cycle are left out, and show up in the
+    /// deadlock report instead.
+    fn stage_levels(&self) -> Vec<HashSet<StageKey>> {
+        let mut stages: StageTable = HashMap::new();
+        self.visit_stages(&mut |_, view| {
+            stages.insert(
+                view.address,
+                view.entries().map(|(kind, who, entry)| (kind, who, entry.reads.clone())).collect()
+            );
+        });
+
+        fn level(key: StageKey, stages: &StageTable, memo: &mut HashMap<StageKey, Option<usize>>) -> Option<usize> {
+            if let Some(known) = memo.get(&key) {
+                return *known;
+            }
+            // Anything that comes back around to this stage before it's done is a cycle.
+            memo.insert(key, None);
+            let (address, kind, who) = key;
+            let empty = vec! [];
+            let reads = stages[&address].iter()
+                .find(|(k, w, _)| *k == kind && *w == who)
+                .map_or(&empty, |(_, _, reads)| reads);
+            let before = stages[&address].iter()
+                .filter(|(k, _, _)| *k < kind)
+                .map(|(k, w, _)| (address, *k, *w));
+            let read = reads.iter()
+                .filter_map(|read| stages.get(read).map(|entries| (*read, entries)))
+                .flat_map(|(read, entries)| entries.iter().map(move |(k, w, _)| (read, *k, *w)));
+            let deps: Vec<StageKey> = before.chain(read).collect();
+
+            let mut result = Some(0);
+            for dep in deps {
+                result = match (result, level(dep, stages, memo)) {
+                    (Some(l), Some(d)) => Some(l.max(d + 1)),
+                    _ => None
+                };
+            }
+            memo.insert(key, result);
+            result
+        }
+
+        let mut memo = HashMap::new();
+        let mut levels: Vec<HashSet<StageKey>> = vec! [];
+        for (address, entries) in &stages {
+            for (kind, who, _) in entries {
+                let key = (*address, *kind, *who);
+                if let Some(l) = level(key, &stages, &mut memo) {
+                    if levels.len() <= l {
+                        levels.resize_with(l + 1, HashSet::new);
+                    }
+                    levels[l].insert(key);
+                }
+            }
+        }
+        levels
+    }
+
+    /// Collects what every stage did, for explaining where each number came from.
+    pub fn breakdown(&self) -> Breakdown {
+        let mut breakdown = Breakdown::new();
+        self.visit_stages(&mut |field, view| {
+            let mut applied: Vec<(StageKind, &StageEntry, &Applied)> = view.entries()
+                .filter_map(|(kind, _, entry)| entry.applied.as_ref().map(|a| (kind, entry, a)))
+                .collect();
+            if applied.is_empty() {
+                return;
+            }
+            applied.sort_by_key(|(_, _, a)| a.order);
+            breakdown.insert(
+                field.to_string(),
+                applied.into_iter().map(|(stage, entry, a)| Contribution {
+                    stage,
+                    source: entry.label.clone(),
+                    delta: a.delta.clone(),
+                    text: match a.delta {
+                        Delta::Modified => format!("modified by {}", entry.label),
+                        _ => format!("{} from {}", a.delta, entry.label)
+                    }
+                }).collect()
+            );
+        });
+        breakdown
+    }
+
+    /// Lists every stage that hasn't run yet.
+    fn deadlock_report(&self) -> DeadlockReport {
+        let mut fields: HashMap<usize, (String, bool)> = HashMap::new();
+        self.visit_stages(&mut |field, view| {
+            fields.insert(view.address, (field.to_string(), view.finalized()));
+        });
+        let mut pending = vec! [];
+        self.visit_stages(&mut |field, view| {
+            for (stage, _, entry) in view.entries().filter(|(_, _, e)| !e.done) {
+                pending.push(PendingStage {
+                    field: field.to_string(),
+                    stage,
+                    id: entry.info.id,
+                    source: entry.source.clone(),
+                    location: entry.info.location,
+                    waiting_on: entry.reads.iter()
+                        .filter_map(|address| fields.get(address))
+                        .find(|(_, finalized)| !finalized)
+                        .map(|(name, _)| name.clone())
+                });
+            }
+        });
+        DeadlockReport { pending }
+    }
+}
+
 pub trait Resolveable {
     type Inner;
 
     fn count_unresolved(&self) -> u32;
     fn unwrap(self) -> Self::Inner;
+
+    /// Calls `f` on every Staged value inside, labelled with its field name.
+    fn visit(&self, field: &str, f: &mut dyn FnMut(&str, StagedView));
+}
+
+/// Which of the three resolution stages an `i!`, `m!`, or `f!` block belongs to.
+#[derive(Debug, Serialize, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum StageKind {
+    Initialize,
+    Modify,
+    Finalize
+}
+
+/// The piece of content whose resolve method is currently running.
+///
+/// Set by [StoredCharacter::resolve] around each call to a content's resolve method, and
+/// recorded by every stage that content registers.
+#[derive(Debug, Serialize, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    CommonRules,
+    Race(&'static str),
+    Class {
+        index: usize,
+        name: &'static str
+    },
+    Background(&'static str),
+    Item {
+        index: usize,
+        name: &'static str,
+        /// How many items with the same name come before this one.
+        occurrence: usize
+    }
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::CommonRules
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::CommonRules => write!(f, "common rules"),
+            Source::Race(name) => write!(f, "race {}", name),
+            Source::Class { index, name } => write!(f, "class {} ({})", index, name),
+            Source::Background(name) => write!(f, "background {}", name),
+            Source::Item { index, name, .. } => write!(f, "item {} ({})", index, name)
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT_SOURCE: RefCell<Source> = RefCell::new(Source::CommonRules);
+}
+
+/// Runs `f` with `source` recorded as the owner of any stages it registers.
+pub(crate) fn with_source<R>(source: Source, f: impl FnOnce() -> R) -> R {
+    let old = CURRENT_SOURCE.with(|current| current.replace(source));
+    let result = f();
+    CURRENT_SOURCE.with(|current| current.replace(old));
+    result
+}
+
+fn current_source() -> Source {
+    CURRENT_SOURCE.with(|current| current.borrow().clone())
+}
+
+thread_local! {
+    static CONTENT: RefCell<Vec<(&'static str, &'static str)>> = RefCell::new(vec! []);
+}
+
+/// Marks a piece of content's resolve method as running, until the guard is dropped.
+///
+/// The `#[content]` macro puts one of these at the top of every resolve method, so content
+/// resolved from inside other content (like a feat chosen by a race) gets a label like
+/// "Alert feat through Variant Human".
+pub fn enter_content(kind: &'static str, name: &'static str) -> ContentGuard {
+    CONTENT.with(|content| content.borrow_mut().push((kind, name)));
+    ContentGuard
+}
+
+pub struct ContentGuard;
+
+impl Drop for ContentGuard {
+    fn drop(&mut self) {
+        CONTENT.with(|content| content.borrow_mut().pop());
+    }
+}
+
+/// A name for a field of the content that is resolving right now, that doesn't change when
+/// elements are added or reordered, like `race:variant human/feat:alert/abilities`.
+///
+/// The stage macros give every [Element::Choice] and [Element::Toggle] one of these, so edits
+/// can find them without counting. Items are told apart by name rather than position, so
+/// removing one doesn't move the ids of the others.
+pub fn element_id(field: &str) -> String {
+    let mut id = match current_source() {
+        Source::Item { name, occurrence, .. } => format!("{}#{}/", name.to_lowercase(), occurrence),
+        _ => String::new()
+    };
+    CONTENT.with(|content| {
+        for (kind, name) in content.borrow().iter() {
+            id += &format!("{}:{}/", kind, name.to_lowercase());
+        }
+    });
+    id + field
+}
+
+/// Human-readable name for whatever is registering stages right now.
+fn current_label() -> String {
+    CONTENT.with(|content| {
+        let content = content.borrow();
+        match content.split_last() {
+            Some(((kind, name), outer)) => {
+                let mut label = format!("{} {}", name, kind);
+                for (_, name) in outer.iter().rev() {
+                    label += &format!(" through {}", name);
+                }
+                label
+            }
+            None => match current_source() {
+                Source::CommonRules => "common rules".to_string(),
+                Source::Race(name)
+                | Source::Class { name, .. }
+                | Source::Background(name)
+                | Source::Item { name, .. } => format!("common rules for {}", name)
+            }
+        }
+    })
+}
+
+/// Compile-time description of a single `i!`, `m!`, or `f!` statement, emitted by the macro.
+///
+/// `writes` is the left hand side and `reads` are the `?` carriers on the right, both as
+/// written in the source. They are only for humans; the actual fields a stage touches are
+/// found at runtime, when it is registered.
+#[derive(Debug, Serialize)]
+pub struct StageInfo {
+    pub id: u64,
+    pub kind: StageKind,
+    pub writes: &'static str,
+    pub reads: &'static [&'static str],
+    pub location: &'static str
+}
+
+/// Bookkeeping for a single initializer, modifier, or finalizer on a [Staged] value.
+///
+/// `reads` holds the addresses of the Staged values that the stage's `?` carriers point at.
+/// `applied` is filled in when the stage runs.
+#[derive(Debug, Serialize, Clone)]
+pub struct StageEntry {
+    pub done: bool,
+    pub info: &'static StageInfo,
+    pub source: Source,
+    pub label: String,
+    pub reads: Vec<usize>,
+    pub applied: Option<Applied>
+}
+
+/// What a stage did to its value, and how many other stages got to it first.
+#[derive(Debug, Serialize, Clone)]
+pub struct Applied {
+    pub order: usize,
+    pub delta: Delta
+}
+
+/// The change a stage made to a value, worked out from the value's JSON before and after.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "op", content = "value", rename_all = "snake_case")]
+pub enum Delta {
+    /// A number went up or down.
+    Add(i64),
+    /// Any other [Tracked] value; the value it was set to.
+    Set(Value),
+    /// A value that isn't tracked, like a list, was changed somehow.
+    Modified
+}
+
+impl Delta {
+    fn between(before: Option<Value>, after: Option<Value>) -> Delta {
+        match (before, after) {
+            (Some(Value::Number(b)), Some(Value::Number(a))) if b.is_i64() || b.is_u64() => {
+                match (b.as_i64(), a.as_i64()) {
+                    (Some(b), Some(a)) => Delta::Add(a - b),
+                    _ => Delta::Set(Value::Number(a))
+                }
+            }
+            (Some(_), Some(after)) => Delta::Set(after),
+            _ => Delta::Modified
+        }
+    }
+}
+
+/// Whether a [Staged] value is saved before and after every stage that writes it, so the
+/// [Breakdown] can say exactly what the stage did.
+///
+/// Lists are left out: some get written by dozens of stages and can be long, so copying them
+/// every time would make resolving quadratic. Their breakdown only says who modified them.
+pub trait Tracked {
+    fn snapshot(&self) -> Option<Value>;
+}
+
+macro_rules! tracked {
+    ($($t:ty),*) => {
+        $(impl Tracked for $t {
+            fn snapshot(&self) -> Option<Value> {
+                serde_json::to_value(self).ok()
+            }
+        })*
+    };
+}
+
+tracked!(u32, i32, bool, String, Option<&'static str>, Option<Armor>, Option<(u32, u32)>,
+    Vantage, ProficiencyType, CreatureSize, Alignment, Encumbrance);
+
+impl<T> Tracked for Vec<T> {
+    fn snapshot(&self) -> Option<Value> {
+        None
+    }
+}
+
+impl Display for Delta {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fn show(v: &Value) -> String {
+            match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string()
+            }
+        }
+        match self {
+            Delta::Add(n) => write!(f, "{:+}", n),
+            Delta::Set(v) => write!(f, "={}", show(v)),
+            Delta::Modified => write!(f, "modified")
+        }
+    }
+}
+
+/// One line of a value's [Breakdown], like "+5 from Alert feat through Variant Human".
+#[derive(Debug, Serialize, Clone)]
+pub struct Contribution {
+    pub stage: StageKind,
+    pub source: String,
+    pub delta: Delta,
+    pub text: String
+}
+
+/// Every stage that changed each field, by field name, in the order they ran.
+pub type Breakdown = BTreeMap<String, Vec<Contribution>>;
+
+impl StageEntry {
+    fn new(info: &'static StageInfo, reads: &[usize]) -> Self {
+        StageEntry {
+            done: false,
+            info,
+            source: current_source(),
+            label: current_label(),
+            reads: reads.to_vec(),
+            applied: None
+        }
+    }
+}
+
+/// A stage, identified by the address of the Staged value it writes, its kind, and its key.
+type StageKey = (usize, StageKind, u64);
+
+/// Every stage's kind, key, and reads, by the address of the Staged value it writes.
+type StageTable = HashMap<usize, Vec<(StageKind, u64, Vec<usize>)>>;
+
+/// Which stages are allowed to run when a `request_*` method is called.
+enum Schedule {
+    /// Whatever is ready.
+    FixedPoint,
+    /// Nothing; just register.
+    Discover,
+    /// Only these.
+    Run(HashSet<StageKey>)
+}
+
+thread_local! {
+    static SCHEDULE: RefCell<Schedule> = RefCell::new(Schedule::FixedPoint);
+}
+
+fn with_schedule<R>(schedule: Schedule, f: impl FnOnce() -> R) -> R {
+    let old = SCHEDULE.with(|current| current.replace(schedule));
+    let result = f();
+    SCHEDULE.with(|current| current.replace(old));
+    result
+}
+
+/// Type-erased view of the bookkeeping of one [Staged] value.
+pub struct StagedView<'a> {
+    pub address: usize,
+    pub initializers: &'a HashMap<u64, StageEntry>,
+    pub modifiers: &'a HashMap<u64, StageEntry>,
+    pub finalizers: &'a HashMap<u64, StageEntry>
+}
+
+impl<'a> StagedView<'a> {
+    /// All entries, in stage order, with the key they are stored under.
+    pub fn entries(&self) -> impl Iterator<Item=(StageKind, u64, &'a StageEntry)> {
+        let tag = |kind: StageKind| move |(who, entry): (&u64, &'a StageEntry)| (kind, *who, entry);
+        self.initializers.iter().map(tag(StageKind::Initialize))
+            .chain(self.modifiers.iter().map(tag(StageKind::Modify)))
+            .chain(self.finalizers.iter().map(tag(StageKind::Finalize)))
+    }
+
+    pub fn finalized(&self) -> bool {
+        self.entries().all(|(_, _, e)| e.done)
+    }
+}
+
+/// A stage that still hasn't run when resolution gives up.
+#[derive(Debug, Serialize, Clone)]
+pub struct PendingStage {
+    pub field: String,
+    pub stage: StageKind,
+    pub id: u64,
+    pub source: Source,
+    pub location: &'static str,
+    pub waiting_on: Option<String>
+}
+
+/// Everything that was still waiting when [StoredCharacter::resolve] stopped making progress.
+#[derive(Debug, Serialize, Clone)]
+pub struct DeadlockReport {
+    pub pending: Vec<PendingStage>
+}
+
+impl Display for DeadlockReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "RESOLUTION DEADLOCK: {} stages pending", self.pending.len())?;
+        for p in &self.pending {
+            write!(f, "    {:<32} {:<10?} stage {:<5} from {} ({})", p.field, p.stage, p.id, p.source, p.location)?;
+            match &p.waiting_on {
+                Some(field) => writeln!(f, ", waiting on {}", field)?,
+                None => writeln!(f)?
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Default, Debug, Serialize)]
 pub struct Staged<T>
     where T: Default + Debug + Serialize {
     value: T,
-    initializers: HashMap<u64, bool>,
-    modifiers: HashMap<u64, bool>,
-    finalizers: HashMap<u64, bool>,
+    initializers: HashMap<u64, StageEntry>,
+    modifiers: HashMap<u64, StageEntry>,
+    finalizers: HashMap<u64, StageEntry>,
 }
 
 impl<T> Staged<T>
@@ -292,64 +904,109 @@ impl<T> Staged<T>
 
 
     fn initialized(&self) -> bool {
-        self.initializers.values().all(|b| *b)
+        self.initializers.values().all(|e| e.done)
     }
     fn modified(&self) -> bool {
-        self.initialized() && self.modifiers.values().all(|b| *b)
+        self.initialized() && self.modifiers.values().all(|e| e.done)
     }
     pub fn finalized(&self) -> bool {
-        self.modified() && self.finalizers.values().all(|b| *b)
+        self.modified() && self.finalizers.values().all(|e| e.done)
     }
 
-    pub fn request_initialize(&mut self, who: u64) -> bool {
+    pub fn request_initialize(&mut self, who: u64, info: &'static StageInfo, reads: &[usize]) -> bool {
         match self.initializers.get(&who) {
-            Some(b) if !*b => true,
+            Some(e) if !e.done => self.scheduled(StageKind::Initialize, who, true),
             None => {
-                self.initializers.insert(who, false);
+                self.initializers.insert(who, StageEntry::new(info, reads));
                 false
             }
             _ => false
         }
     }
-    pub fn request_modify(&mut self, who: u64) -> bool {
+    pub fn request_modify(&mut self, who: u64, info: &'static StageInfo, reads: &[usize]) -> bool {
         match self.modifiers.get(&who) {
-            Some(b) if !*b => self.initialized(),
+            Some(e) if !e.done => self.scheduled(StageKind::Modify, who, self.initialized()),
             None => {
-                self.modifiers.insert(who, false);
+                self.modifiers.insert(who, StageEntry::new(info, reads));
                 false
             }
             _ => false
         }
     }
-    pub fn request_finalize(&mut self, who: u64) -> bool {
+    pub fn request_finalize(&mut self, who: u64, info: &'static StageInfo, reads: &[usize]) -> bool {
         match self.finalizers.get(&who) {
-            Some(b) if !*b => self.initialized() && self.modified(),
+            Some(e) if !e.done => self.scheduled(StageKind::Finalize, who, self.initialized() && self.modified()),
             None => {
-                self.finalizers.insert(who, false);
+                self.finalizers.insert(who, StageEntry::new(info, reads));
                 false
             }
             _ => false
         }
     }
 
-    pub fn confirm_initialize(&mut self, who: u64) {
+    fn scheduled(&self, kind: StageKind, who: u64, ready: bool) -> bool {
+        SCHEDULE.with(|schedule| match &*schedule.borrow() {
+            Schedule::FixedPoint => ready,
+            Schedule::Discover => false,
+            Schedule::Run(allowed) => ready && allowed.contains(&(self.address(), kind, who))
+        })
+    }
+
+    pub(crate) fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl<T> Staged<T>
+    where T: Serialize + Default + Debug + Tracked {
+    pub fn confirm_initialize(&mut self, who: u64, before: Option<Value>) {
+        let applied = self.applied(before);
         match self.initializers.get_mut(&who) {
-            Some(b) if !*b => *b = true,
+            Some(e) if !e.done => {
+                e.done = true;
+                e.applied = Some(applied);
+            }
             _ => panic!("nope")
         }
     }
-    pub fn confirm_modify(&mut self, who: u64) {
+    pub fn confirm_modify(&mut self, who: u64, before: Option<Value>) {
+        let applied = self.applied(before);
         match self.modifiers.get_mut(&who) {
-            Some(b) if !*b => *b = true,
+            Some(e) if !e.done => {
+                e.done = true;
+                e.applied = Some(applied);
+            }
             _ => panic!("nope")
         }
     }
-    pub fn confirm_finalize(&mut self, who: u64) {
+    pub fn confirm_finalize(&mut self, who: u64, before: Option<Value>) {
+        let applied = self.applied(before);
         match self.finalizers.get_mut(&who) {
-            Some(b) if !*b => *b = true,
+            Some(e) if !e.done => {
+                e.done = true;
+                e.applied = Some(applied);
+            }
             _ => panic!("nope")
         }
     }
+
+    /// The value as JSON if it is [Tracked], to pass back to `confirm_*` after writing so the
+    /// change can be recorded.
+    pub fn snapshot(&self) -> Option<Value> {
+        self.value.snapshot()
+    }
+
+    fn applied(&self, before: Option<Value>) -> Applied {
+        let order = self.initializers.values()
+            .chain(self.modifiers.values())
+            .chain(self.finalizers.values())
+            .filter(|e| e.done)
+            .count();
+        Applied {
+            order,
+            delta: Delta::between(before, self.snapshot())
+        }
+    }
 }
 
 impl<T> Resolveable for Staged<T>
@@ -357,13 +1014,21 @@ impl<T> Resolveable for Staged<T>
     type Inner = T;
 
     fn count_unresolved(&self) -> u32 {
-        self.initializers.values().fold(0, |acc, b| acc + !*b as u32)
-            + self.modifiers.values().fold(0, |acc, b| acc + !*b as u32)
-            + self.finalizers.values().fold(0, |acc, b| acc + !*b as u32)
+        self.initializers.values().fold(0, |acc, e| acc + !e.done as u32)
+            + self.modifiers.values().fold(0, |acc, e| acc + !e.done as u32)
+            + self.finalizers.values().fold(0, |acc, e| acc + !e.done as u32)
     }
     fn unwrap(self) -> T {
     self.value
 }
+    fn visit(&self, field: &str, f: &mut dyn FnMut(&str, StagedView)) {
+        f(field, StagedView {
+            address: self.address(),
+            initializers: &self.initializers,
+            modifiers: &self.modifiers,
+            finalizers: &self.finalizers
+        })
+    }
 }
 
 impl<T> Staged<T>
@@ -407,9 +1072,206 @@ impl<T> Resolveable for Vec<Staged<T>>
     fn unwrap(self) -> Vec<T> {
         self.into_iter().map(|stage| stage.unwrap()).collect()
     }
+
+    fn visit(&self, field: &str, f: &mut dyn FnMut(&str, StagedView)) {
+        for (i, stage) in self.iter().enumerate() {
+            stage.visit(&format!("{}[{}]", field, i), f);
+        }
+    }
 }
 
-unsafe impl Sync for StoredCharacter {}
-unsafe impl Send for StoredCharacter {}
-unsafe impl Sync for FinalCharacter {}
-unsafe impl Send for FinalCharacter {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_strategies_agree(stored: &mut StoredCharacter) {
+        let fixed_point = stored.resolve_with(ResolutionStrategy::FixedPoint)
+            .expect("fixed point resolution deadlocked");
+        let topological = stored.resolve_with(ResolutionStrategy::Topological)
+            .expect("topological resolution deadlocked");
+        assert_eq!(
+            serde_json::to_value(&fixed_point).unwrap(),
+            serde_json::to_value(&topological).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_character() {
+        assert_strategies_agree(&mut StoredCharacter::read("test_character.json"));
+    }
+
+    #[test]
+    fn default_character() {
+        assert_strategies_agree(&mut StoredCharacter::default());
+    }
+
+    #[test]
+    fn lists_are_not_snapshotted() {
+        let c = StoredCharacter::read("test_character.json").resolve().unwrap();
+        assert!(c.breakdown["moves"].iter().all(|m| m.delta == Delta::Modified && m.text.starts_with("modified by ")));
+        assert_eq!(c.breakdown["size"][0].delta, Delta::Set(serde_json::json!("Medium")));
+        assert!(c.breakdown["abilities.dexterity"].iter().any(|d| matches!(d.delta, Delta::Add(_))));
+    }
+
+    #[test]
+    fn elements_are_edited_through_their_ids() {
+        use crate::ui::{edit_element, ElementEdit, ElementEditError};
+
+        let mut stored = StoredCharacter {
+            race: crate::content::race("Halfling").unwrap(),
+            ..Default::default()
+        };
+        let choose = |choice: &str| ElementEdit::Choose { choice: choice.to_string(), index: 0 };
+        assert_eq!(edit_element("race:halfling/subrace", choose("Stout"), || stored.run_content()), Ok(()));
+        let resolved = serde_json::to_string(&stored.resolve().unwrap()).unwrap();
+        assert!(resolved.contains("[Stout] Ability Score Increase"));
+
+        assert_eq!(
+            edit_element("race:halfling/subrace", ElementEdit::Toggle { index: 0 }, || stored.run_content()),
+            Err(ElementEditError::NotAToggle)
+        );
+        assert_eq!(
+            edit_element("race:halfling/nothing", choose("Stout"), || stored.run_content()),
+            Err(ElementEditError::NotFound)
+        );
+    }
+
+    #[test]
+    fn resources_are_spent_and_recharged() {
+        use crate::ui::{edit_element, ElementEdit, ElementEditError, ResourceChange};
+
+        let mut stored = StoredCharacter::read("test_character.json");
+        let luck = "class:rogue/stroke_of_luck";
+        let change = |change| ElementEdit::Resource { change };
+        let left = |stored: &mut StoredCharacter| {
+            let json = serde_json::to_value(&stored.resolve().unwrap()).unwrap();
+            json["moves"].as_array().unwrap().iter()
+                .find(|m| m["element"]["id"] == luck)
+                .map(|m| m["element"]["data"].clone())
+                .unwrap()
+        };
+
+        assert_eq!(left(&mut stored), 1);
+        assert_eq!(edit_element(luck, change(ResourceChange::Decrement), || stored.run_content()), Ok(()));
+        assert_eq!(left(&mut stored), 0);
+        assert_eq!(
+            edit_element(luck, change(ResourceChange::Decrement), || stored.run_content()),
+            Err(ElementEditError::OutOfRange { max: 1 })
+        );
+        assert_eq!(
+            edit_element(luck, change(ResourceChange::Set(2)), || stored.run_content()),
+            Err(ElementEditError::OutOfRange { max: 1 })
+        );
+
+        stored.event(Event::Other("nothing"));
+        assert_eq!(left(&mut stored), 0);
+        stored.event(Event::ShortRest);
+        assert_eq!(left(&mut stored), 1);
+    }
+
+    #[test]
+    fn panics_dont_leave_edits_or_rests_behind() {
+        use crate::ui::{edit_element, rest, ElementEdit, ResourceChange};
+
+        let mut stored = StoredCharacter::read("test_character.json");
+        let luck = "class:rogue/stroke_of_luck";
+        let spend = || ElementEdit::Resource { change: ResourceChange::Decrement };
+        let left = |stored: &mut StoredCharacter| {
+            let json = serde_json::to_value(&stored.resolve().unwrap()).unwrap();
+            json["moves"].as_array().unwrap().iter()
+                .find(|m| m["element"]["id"] == luck)
+                .map(|m| m["element"]["data"].clone())
+                .unwrap()
+        };
+
+        assert!(std::panic::catch_unwind(|| edit_element(luck, spend(), || panic!("resolve failed"))).is_err());
+        assert_eq!(left(&mut stored), 1);
+        assert_eq!(edit_element(luck, spend(), || stored.run_content()), Ok(()));
+        assert!(std::panic::catch_unwind(|| rest(&Event::LongRest, || panic!("resolve failed"))).is_err());
+        assert_eq!(left(&mut stored), 0);
+    }
+
+    #[test]
+    fn item_element_ids_dont_move() {
+        fn cloak_ids(stored: &mut StoredCharacter) -> Vec<String> {
+            let resolved = serde_json::to_value(stored.resolve().unwrap()).unwrap();
+            resolved["moves"].as_array().unwrap().iter()
+                .filter_map(|m| m["element"]["id"].as_str())
+                .filter(|id| id.contains("cloak of elvenkind"))
+                .map(str::to_string)
+                .collect()
+        }
+
+        let mut stored = StoredCharacter {
+            inventory: vec! [
+                (crate::content::item("Longsword").unwrap(), Equipped::No, false),
+                (crate::content::item("Cloak of Elvenkind").unwrap(), Equipped::Yes, true)
+            ],
+            ..StoredCharacter::default()
+        };
+        let before = cloak_ids(&mut stored);
+        assert!(!before.is_empty());
+
+        stored.inventory.remove(0);
+        assert_eq!(cloak_ids(&mut stored), before);
+    }
+
+    #[test]
+    fn big_purses_dont_overflow_the_carried_weight() {
+        let mut stored = StoredCharacter {
+            money: MoneyTypeMap {
+                platinum: 0,
+                gold: u32::MAX,
+                electrum: 0,
+                silver: u32::MAX,
+                copper: 0,
+            },
+            ..StoredCharacter::default()
+        };
+        assert_eq!(stored.resolve().unwrap().carried_weight, (2 * u32::MAX as u64 / 50) as u32);
+    }
+
+    #[test]
+    fn attacks_wait_for_abilities_raised_by_improvements() {
+        // The longsword always uses Strength, so nothing else makes its attack wait for the
+        // Strength modifier, which the rogue's improvements at 4th level and up raise.
+        let mut stored = StoredCharacter::read("test_character.json");
+        stored.inventory = vec! [(crate::content::item("Longsword").unwrap(), Equipped::Held(Hand::Both), false)];
+        stored.race = Default::default();
+        stored.background = Default::default();
+        let graph = stored.dependency_graph();
+        assert!(graph.stages.iter().any(|stage| stage.writes == "moves"
+            && stage.kind == StageKind::Modify
+            && stage.reads.iter().any(|read| read == "ability_modifiers.strength")));
+        for level in 1..=20 {
+            stored.classes[0].1 = level;
+            assert_strategies_agree(&mut stored);
+            let c = serde_json::to_value(stored.resolve().unwrap()).unwrap();
+            let longsword = c["moves"].as_array().unwrap().iter()
+                .find(|m| m["name"] == "Longsword")
+                .expect("no longsword attack");
+            let proficient = c["weapon_proficiencies"].as_array().unwrap().contains(&longsword["name"]);
+            let expected = c["ability_modifiers"]["strength"].as_i64().unwrap()
+                + if proficient { c["proficiency_bonus"].as_i64().unwrap() } else { 0 };
+            assert_eq!(longsword["hit"].as_i64().unwrap(), expected, "at level {}", level);
+        }
+    }
+
+    #[test]
+    fn every_race_and_background_with_every_class_level() {
+        for race in crate::content::get_all_race() {
+            for background in crate::content::get_all_background() {
+                for class in crate::content::get_all_class() {
+                    for level in 1..=20 {
+                        assert_strategies_agree(&mut StoredCharacter {
+                            race: crate::content::race(race).unwrap(),
+                            background: crate::content::background(background).unwrap(),
+                            classes: vec! [(crate::content::class(class).unwrap(), level)],
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,8 @@
 pub(crate) mod common_rules {
-    use crate::character::Character;
-    use crate::misc::{Ability, ProficiencyType, Skill, PassiveSkill};
+    use crate::character::{Character, StageInfo, StageKind};
+    use crate::misc::{Ability, ProficiencyType, Skill, PassiveSkill, MovementType, Encumbrance};
     use enum_iterator::IntoEnumIterator;
-    use proc_macros::{i, unique_id};
+    use proc_macros::{i, m, f, unique_id};
     use crate::moves::Move;
 
     pub fn resolve(c: &mut Character) {
@@ -20,7 +20,7 @@ pub(crate) mod common_rules {
         i! {
             c.proficiency_bonus = {
                 let level = c.total_level?;
-                if level >= 1 && level <= 20 {
+                if (1..=20).contains(&level) {
                     (level - 1) / 4 + 2
                 } else {
                     0
@@ -28,13 +28,10 @@ pub(crate) mod common_rules {
             };
             c.initiative = c.ability_modifiers.dexterity?;
             c.attacks_per_action = 1;
-            c.armor_class = {
-                let default = (10 + c.ability_modifiers.dexterity?) as u32;
-                if *c.armor_class < default {
-                    default
-                } else {
-                    *c.armor_class
-                }
+            c.armor_class = match c.worn_armor? {
+                Some(armor) => armor.armor_class(c.ability_modifiers.dexterity?),
+                None => c.unarmored_defenses?.into_iter()
+                    .fold((10 + c.ability_modifiers.dexterity?).max(0) as u32, u32::max)
             };
         }
 
@@ -64,54 +61,115 @@ pub(crate) mod common_rules {
             }
         }
 
+        // CARRYING
+        i! {
+            c.carried_weight = {
+                let coins = [c.money.platinum?, c.money.gold?, c.money.electrum?, c.money.silver?, c.money.copper?];
+                (coins.iter().map(|n| *n as u64).sum::<u64>() / 50).min(u32::MAX as u64) as u32
+            };
+            c.carrying_capacity = c.size?.carrying(15 * c.abilities.strength?);
+            c.push_drag_lift = c.size?.carrying(30 * c.abilities.strength?);
+            c.encumbrance_thresholds = if c.variant_encumbrance? {
+                let capacity = c.carrying_capacity?;
+                Some((capacity / 3, capacity * 2 / 3))
+            } else {
+                None
+            };
+            c.encumbrance = Encumbrance::of(c.carried_weight?, c.carrying_capacity?, c.encumbrance_thresholds?);
+        }
+
         // MODIFIERS
 
-        // ATTACK SKILL MODIFIERS
-        let id = unique_id!();
-        if c.moves.request_modify(id) {
-            let mut ready = true;
-            for r#move in &*c.moves {
-                if let Move::Attack { use_modifier, ..} = r#move {
-                    if let Some(modifier) = c.ability_modifiers.get(*use_modifier) {
-                        if !modifier.finalized() {
-                            ready = false;
-                            break;
-                        }
-                    }
+        // ENCUMBRANCE
+        for skill in Skill::into_enum_iter() {
+            if skill.known() && is_physical(skill.get_associated_ability()) {
+                m! {
+                    *c.skill_vantages.get_mut_known(skill) = (**c.skill_vantages.get_known(skill))
+                        .downgraded_if(c.encumbrance? == Encumbrance::HeavilyEncumbered)
+                }
+            }
+        }
+        for ability in Ability::into_enum_iter() {
+            if ability.known() && is_physical(ability) {
+                m! {
+                    *c.save_vantages.get_mut_known(ability) = (**c.save_vantages.get_known(ability))
+                        .downgraded_if(c.encumbrance? == Encumbrance::HeavilyEncumbered)
                 }
             }
+        }
 
-            if ready &&
-                c.proficiency_bonus.finalized() &&
-                c.weapon_proficiencies.finalized() {
+        // ATTACK SKILL MODIFIERS
+        static ATTACK_MODIFIERS: StageInfo = StageInfo {
+            id: unique_id!(),
+            kind: StageKind::Modify,
+            writes: "c.moves",
+            reads: &["c.ability_modifiers", "c.proficiency_bonus", "c.weapon_proficiencies"],
+            location: concat!(file!(), ":", line!())
+        };
+        let id = ATTACK_MODIFIERS.id;
+        // Only the abilities that attacks actually use have to be final.
+        let used: Vec<Ability> = c.moves.iter()
+            .filter_map(|r#move| match r#move {
+                Move::Attack { use_modifier, .. } => Some(*use_modifier),
+                _ => None
+            })
+            .collect();
+        // Moves are still being added when the stage registers, so it reads every modifier.
+        let reads: Vec<usize> = Ability::into_enum_iter()
+            .filter_map(|ability| c.ability_modifiers.get(ability))
+            .map(|modifier| modifier.address())
+            .chain(vec! [c.proficiency_bonus.address(), c.weapon_proficiencies.address()])
+            .collect();
+        if c.moves.request_modify(id, &ATTACK_MODIFIERS, &reads) {
+            let ready = used.iter()
+                .filter_map(|ability| c.ability_modifiers.get(*ability))
+                .all(|modifier| modifier.r#final().is_ok())
+                && c.proficiency_bonus.r#final().is_ok()
+                && c.weapon_proficiencies.r#final().is_ok();
+
+            if ready {
+                let before = c.moves.snapshot();
                 for r#move in &mut *c.moves {
-                    match r#move {
-                        Move::Attack {
-                            name,
-                            use_modifier,
-                            hit,
-                            ..
-                        } => {
-                            if let Some(modifier) = c.ability_modifiers.get(*use_modifier) {
-                                *hit += **modifier
-                            }
-                            if (*c.weapon_proficiencies).contains(name) {
-                                *hit += *c.proficiency_bonus as i32;
-                            }
+                    if let Move::Attack {
+                        name,
+                        use_modifier,
+                        hit,
+                        ..
+                    } = r#move {
+                        if let Some(modifier) = c.ability_modifiers.get(*use_modifier) {
+                            *hit += **modifier
+                        }
+                        if (*c.weapon_proficiencies).contains(name) {
+                            *hit += *c.proficiency_bonus as i32;
                         }
-                        _ => {}
                     }
                 }
-                c.moves.confirm_modify(id);
+                c.moves.confirm_modify(id, before);
+            }
+        }
+
+        // FINALIZERS
+
+        // ENCUMBRANCE
+        for movement in MovementType::into_enum_iter() {
+            if movement.known() {
+                f! { *c.speeds.get_mut_known(movement) = c.encumbrance?.speed(**c.speeds.get_known(movement)) }
             }
         }
+
         i! {
             c.race_choices = crate::content::get_all_race();
             c.background_choices = crate::content::get_all_background();
             c.class_choices = crate::content::get_all_class();
+            c.item_choices = crate::content::get_all_item();
         }
     }
 
+    /// Whether heavy encumbrance gives disadvantage on checks and saves with `ability`.
+    fn is_physical(ability: Ability) -> bool {
+        matches!(ability, Ability::Strength | Ability::Dexterity | Ability::Constitution)
+    }
+
     fn calculate_proficiency(bonus: u32, proficiency: ProficiencyType) -> i32 {
         match proficiency {
             ProficiencyType::None => 0,
@@ -127,7 +185,7 @@ pub(crate) mod common_class_rules {
     use crate::content::traits::Class;
     use proc_macros::i;
 
-    pub fn resolve(c: &mut Character, class: &Box<dyn Class>, level: u32, index: usize) {
+    pub fn resolve(c: &mut Character, class: &dyn Class, level: u32, index: usize) {
         let hd = class.hit_dice();
         i! {
             index;
@@ -151,7 +209,7 @@ pub(crate) mod common_race_rules {
     use crate::content::traits::Race;
     use proc_macros::i;
 
-    pub fn resolve(c: &mut Character, race: &Box<dyn Race>) {
+    pub fn resolve(c: &mut Character, race: &dyn Race) {
         i! { c.race_name = race.name().to_string() }
     }
 }
@@ -161,27 +219,149 @@ pub(crate) mod common_background_rules {
     use crate::content::traits::Background;
     use proc_macros::i;
 
-    pub fn resolve(c: &mut Character, background: &Box<dyn Background>) {
+    pub fn resolve(c: &mut Character, background: &dyn Background) {
         i! { c.background_name = background.name().to_string()}
     }
 }
 
 pub(crate) mod common_item_rules {
     use crate::content::traits::Item;
-    use proc_macros::i;
-    use crate::misc::{Equipped, Equipable, Holdable, Hand};
+    use proc_macros::{i, m};
+    use crate::misc::{Armor, ArmorCategory, Equipped, Equipable, Holdable, Hand};
     use crate::character::Character;
+    use serde::Serialize;
+    use std::fmt::{Display, Formatter};
+
+    /// How many items a character can be attuned to at once.
+    pub const MAX_ATTUNED: usize = 3;
+
+    /// Something about the inventory that breaks the rules. Edits that would cause one are turned
+    /// down, and any already in the character file are shown on the sheet as warnings.
+    #[derive(Debug, Serialize, Clone, Eq, PartialEq)]
+    #[serde(tag = "problem", rename_all = "snake_case")]
+    pub enum InventoryProblem {
+        CannotEquip { name: &'static str, equipable: Equipable, equipped: Equipped },
+        NotAttunable { name: &'static str },
+        /// More than one item is held in `hand`. Two-handed items are held in both.
+        HandInUse { hand: Hand, items: Vec<&'static str> },
+        TooMuchArmor { items: Vec<&'static str> },
+        TooManyShields { items: Vec<&'static str> },
+        TooManyAttuned { items: Vec<&'static str> }
+    }
 
-    pub fn resolve(c: &mut Character, item: &Box<dyn Item>, equipped: Equipped, _attuned: bool) {
+    impl Display for InventoryProblem {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                InventoryProblem::CannotEquip { name, equipped, .. } => write!(f, "{} can't be equipped as {:?}.", name, equipped),
+                InventoryProblem::NotAttunable { name } => write!(f, "{} can't be attuned to.", name),
+                InventoryProblem::HandInUse { hand: Hand::Both, items } => write!(f, "Only one item can be held in both hands, not {}.", items.join(" and ")),
+                InventoryProblem::HandInUse { hand, items } => write!(f, "Only one item can be held in the {:?} hand, not {}.", hand, items.join(" and ")),
+                InventoryProblem::TooMuchArmor { items } => write!(f, "Only one set of armor can be worn, not {}.", items.join(" and ")),
+                InventoryProblem::TooManyShields { items } => write!(f, "Only one shield can be used at a time, not {}.", items.join(" and ")),
+                InventoryProblem::TooManyAttuned { items } => write!(f, "At most {} items can be attuned, not {}.", MAX_ATTUNED, items.join(", "))
+            }
+        }
+    }
+
+    /// Everything wrong with how the `inventory` is equipped.
+    pub fn check(inventory: &[(Box<dyn Item>, Equipped, bool)]) -> Vec<InventoryProblem> {
+        let mut problems = vec! [];
+        let (mut left, mut right, mut armor, mut shields, mut attuned) = (vec! [], vec! [], vec! [], vec! [], vec! []);
+        for (item, equipped, is_attuned) in inventory {
+            let (name, equipable) = (item.name(), item.equipable());
+            if !equipable.allows(*equipped) {
+                problems.push(InventoryProblem::CannotEquip { name, equipable, equipped: *equipped });
+                continue;
+            }
+            match (equipable, equipped) {
+                (Equipable::Armor, Equipped::Yes) => armor.push(name),
+                (_, Equipped::Held(Hand::Left)) => left.push(name),
+                (_, Equipped::Held(Hand::Right)) => right.push(name),
+                (_, Equipped::Held(Hand::Both)) => {
+                    left.push(name);
+                    right.push(name);
+                }
+                _ => {}
+            }
+            if let (Equipped::Held(_), Some(Armor { category: ArmorCategory::Shield, .. })) = (equipped, item.armor()) {
+                shields.push(name);
+            }
+            if *is_attuned {
+                if item.attunable() {
+                    attuned.push(name);
+                } else {
+                    problems.push(InventoryProblem::NotAttunable { name });
+                }
+            }
+        }
+        if left.len() > 1 && left == right {
+            problems.push(InventoryProblem::HandInUse { hand: Hand::Both, items: left });
+        } else {
+            for (hand, items) in vec! [(Hand::Left, left), (Hand::Right, right)] {
+                if items.len() > 1 {
+                    problems.push(InventoryProblem::HandInUse { hand, items });
+                }
+            }
+        }
+        if armor.len() > 1 {
+            problems.push(InventoryProblem::TooMuchArmor { items: armor });
+        }
+        if shields.len() > 1 {
+            problems.push(InventoryProblem::TooManyShields { items: shields });
+        }
+        if attuned.len() > MAX_ATTUNED {
+            problems.push(InventoryProblem::TooManyAttuned { items: attuned });
+        }
+        problems
+    }
+
+    /// Whether a versatile item held in `hand` can be swung with both hands, because nothing else
+    /// is held in the other one.
+    pub fn versatile_two_handed(hand: Hand, left_hand: Option<&str>, right_hand: Option<&str>, both_hands: Option<&str>) -> bool {
+        match hand {
+            Hand::Left => right_hand.is_none() && both_hands.is_none(),
+            Hand::Right => left_hand.is_none() && both_hands.is_none(),
+            Hand::Both => left_hand.is_none() && right_hand.is_none()
+        }
+    }
+
+    /// Items in a state [check] complains about are left out, instead of guessing what was meant.
+    pub fn resolve(c: &mut Character, item: &dyn Item, equipped: Equipped, _attuned: bool, index: usize) {
+        if let Some(weight) = item.weight() {
+            m! {
+                index;
+                c.carried_weight += weight
+            }
+        }
+        if !item.equipable().allows(equipped) {
+            return;
+        }
         match item.equipable() {
             Equipable::Armor => {
                 match equipped {
-                    Equipped::Yes => i! { c.armor = Some(item.name()) },
+                    Equipped::Yes => {
+                        i! { c.armor = Some(item.name()) }
+                        if let Some(armor) = item.armor() {
+                            i! { c.worn_armor = Some(armor) }
+                            if armor.stealth_disadvantage {
+                                m! { c.skill_vantages.stealth -= 1 }
+                            }
+                            if let Some(strength) = armor.strength {
+                                m! {
+                                    c.speeds.walk = if c.abilities.strength? < strength {
+                                        (*c.speeds.walk).saturating_sub(10)
+                                    } else {
+                                        *c.speeds.walk
+                                    }
+                                }
+                            }
+                        }
+                    }
                     Equipped::No => i! {
                         item.name();
                         c.armor_choices <<= item.name();
                     },
-                    Equipped::Held(_) => panic!("armor isn't holdable")
+                    _ => {}
                 }
             }
             Equipable::Holdable(hold) => {
@@ -193,12 +373,15 @@ pub(crate) mod common_item_rules {
                                 item.name();
                                 c.ammunition_choices <<= item.name();
                             },
-                            Equipped::Held(_) => panic!("ammunition isn't holdable")
+                            _ => {}
                         }
                     }
                     _ => {
                         match equipped {
                             Equipped::Held(hand) => {
+                                if let Some(Armor { category: ArmorCategory::Shield, base, .. }) = item.armor() {
+                                    m! { c.armor_class += base }
+                                }
                                 match hand {
                                     Hand::Left => i! { c.left_hand = Some(item.name()) },
                                     Hand::Right => i! { c.right_hand = Some(item.name()) },
@@ -209,7 +392,7 @@ pub(crate) mod common_item_rules {
                                 item.name();
                                 c.hold_choices <<= item.name();
                             },
-                            Equipped::Yes => panic!("holdables aren't generically equippable")
+                            _ => {}
                         }
                     }
                 }
@@ -217,4 +400,4 @@ pub(crate) mod common_item_rules {
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,29 @@
+crate::name!("Chain Mail");
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChainMail;
+
+#[content]
+impl Item for ChainMail {
+    properties! {
+        equipable: Equipable = Equipable::Armor,
+        rarity: Rarity = Rarity::Common,
+        weight: Option<u32> = Some(55),
+        cost: Option<u32> = Some(75),
+        armor: Option<Armor> = Some(Armor {
+            category: ArmorCategory::Heavy,
+            base: 16,
+            dex_cap: Some(0),
+            strength: Some(13),
+            stealth_disadvantage: true
+        })
+    }
+
+    description! {r#"
+        # Chain Mail
+
+        Made of interlocking metal rings, chain mail includes a layer of quilted fabric worn underneath the mail to cushion the chafing and to muffle the sound of rings rubbing against one another. The suit includes gauntlets.
+
+        If your Strength score is lower than 13, your speed is reduced by 10 feet. You have disadvantage on Dexterity (Stealth) checks while wearing it.
+    "#}
+}
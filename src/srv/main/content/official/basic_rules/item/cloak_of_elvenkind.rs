@@ -17,30 +17,28 @@ impl Item for CloakOfElvenkind {
     }
 
     fn resolve(&mut self, c: &mut Character, equipped: Equipped, attuned: bool) {
-        if attuned {
-            if equipped == Equipped::Yes {
-                if self.active {
-                    m! { c.skill_vantages.stealth += 1 }
-                }
-                i! {
-                    c.moves <<= Move::Other {
-                        element: Element::Toggle {
-                            text: if self.active {
-                                "**Cloak of Elvenkind:** Pull the hood down to deactivate."
-                            } else {
-                                "**Cloak of Elvenkind:** Pull the hood up to activate."
-                            },
-                            data: &mut self.active,
-                            button: vec! [
-                                if self.active {
-                                    "Pull Down"
-                                } else {
-                                    "Pull Up"
-                                }
-                            ]
+        if attuned && equipped == Equipped::Yes {
+            if self.active {
+                m! { c.skill_vantages.stealth += 1 }
+            }
+            i! {
+                c.moves <<= Move::Other {
+                    element: Element::Toggle {
+                        text: if self.active {
+                            "**Cloak of Elvenkind:** Pull the hood down to deactivate."
+                        } else {
+                            "**Cloak of Elvenkind:** Pull the hood up to activate."
                         },
-                        time: MoveTime::Action
-                    }
+                        data: &mut self.active,
+                        button: vec! [
+                            if self.active {
+                                "Pull Down"
+                            } else {
+                                "Pull Up"
+                            }
+                        ]
+                    },
+                    time: MoveTime::Action
                 }
             }
         }
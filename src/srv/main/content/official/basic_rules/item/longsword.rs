@@ -13,36 +13,23 @@ impl Item for Longsword {
     }
 
     fn resolve(&mut self, c: &mut Character, equipped: Equipped, _attuned: bool) {
-        match equipped {
-            Equipped::Held(Hand::Both) => {
-                i! {
-                    c.moves <<= Move::Attack {
-                        name: name!(),
-                        time: MoveTime::Action,
-                        hit: 0,
-                        damage: Damage::from_die(10, DamageType::Slashing),
-                        range: Range::Fixed(5),
-                        properties: vec![ "Versatile" ],
-                        use_modifier: Ability::Strength,
-                        weapon_type: WeaponType::Martial
-                    }
+        if let Equipped::Held(hand) = equipped {
+            i! {
+                c.moves <<= Move::Attack {
+                    name: name!(),
+                    time: MoveTime::Action,
+                    hit: 0,
+                    damage: if common_item_rules::versatile_two_handed(hand, c.left_hand?, c.right_hand?, c.both_hands?) {
+                        Damage::from_die(10, DamageType::Slashing)
+                    } else {
+                        Damage::from_die(8, DamageType::Slashing)
+                    },
+                    range: Range::Fixed(5),
+                    properties: vec![ "Versatile" ],
+                    use_modifier: Ability::Strength,
+                    weapon_type: WeaponType::Martial
                 }
             }
-            Equipped::Held(_) => {
-                i! {
-                    c.moves <<= Move::Attack {
-                        name: name!(),
-                        time: MoveTime::Action,
-                        hit: 0,
-                        damage: Damage::from_die(8, DamageType::Slashing),
-                        range: Range::Fixed(5),
-                        properties: vec![ "Versatile" ],
-                        use_modifier: Ability::Strength,
-                        weapon_type: WeaponType::Martial
-                    }
-                }
-            }
-            _ => {}
         }
     }
     description! {r#"
@@ -3,6 +3,7 @@ crate::register! {
     cloak_of_elvenkind
     shield
     padded
+    chain_mail
     rapier
     longsword
     shortsword
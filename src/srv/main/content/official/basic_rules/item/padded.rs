@@ -9,14 +9,14 @@ impl Item for Padded {
         equipable: Equipable = Equipable::Armor,
         rarity: Rarity = Rarity::Common,
         weight: Option<u32> = Some(8),
-        cost: Option<u32> = Some(5)
-    }
-
-    fn resolve(&mut self, c: &mut Character, e: Equipped, _: bool) {
-        if e == Equipped::Yes {
-            i! { c.armor_class = 11 + c.ability_modifiers.dexterity? as u32 }
-            m! { c.skill_vantages.stealth -= 1 }
-        }
+        cost: Option<u32> = Some(5),
+        armor: Option<Armor> = Some(Armor {
+            category: ArmorCategory::Light,
+            base: 11,
+            dex_cap: None,
+            strength: None,
+            stealth_disadvantage: true
+        })
     }
 
     description! {r#"
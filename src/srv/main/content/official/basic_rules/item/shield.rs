@@ -9,16 +9,14 @@ impl Item for Shield {
         equipable: Equipable = Equipable::Holdable(Holdable::One),
         rarity: Rarity = Rarity::Common,
         weight: Option<u32> = Some(6),
-        cost: Option<u32> = Some(10)
-    }
-
-    fn resolve(&mut self, c: &mut Character, e: Equipped, _: bool) {
-        match e {
-            Equipped::Held(_) => {
-                m! { c.armor_class += 2 }
-            }
-            _ => {}
-        }
+        cost: Option<u32> = Some(10),
+        armor: Option<Armor> = Some(Armor {
+            category: ArmorCategory::Shield,
+            base: 2,
+            dex_cap: None,
+            strength: None,
+            stealth_disadvantage: false
+        })
     }
 
     description! {r#"
@@ -9,7 +9,8 @@ pub struct Rogue {
     first_expertise: [RogueExpertiseChoice; 2],
     sixth_expertise: [RogueExpertiseChoice; 2],
 
-    luck: bool
+    /// Uses spent.
+    stroke_of_luck: u32
 }
 
 #[content]
@@ -44,21 +45,16 @@ impl Class for Rogue {
         }
 
         for skill in &self.skill_proficiencies {
-            match c.skill_proficiencies.get_mut(skill.into()) {
-                Some(s) => i!{ *s = ProficiencyType::Single },
-                None => {}
+            if let Some(s) = c.skill_proficiencies.get_mut(skill.into()) {
+                i!{ *s = ProficiencyType::Single }
             }
         }
 
         for expertise in &self.first_expertise {
-            match expertise.into() {
-                Some(skill) => {
-                    match c.skill_proficiencies.get_mut(skill) {
-                        Some(s) => m! { *s = ProficiencyType::Double },
-                        None => {}
-                    }
+            if let Some(skill) = expertise.into() {
+                if let Some(s) = c.skill_proficiencies.get_mut(skill) {
+                    m! { *s = ProficiencyType::Double }
                 }
-                None => {}
             }
         }
 
@@ -173,14 +169,10 @@ impl Class for Rogue {
 
         if level >= 6 {
             for expertise in &self.sixth_expertise {
-                match expertise.into() {
-                    Some(skill) => {
-                        match c.skill_proficiencies.get_mut(skill) {
-                            Some(s) => m! { *s = ProficiencyType::Double },
-                            None => {}
-                        }
+                if let Some(skill) = expertise.into() {
+                    if let Some(s) = c.skill_proficiencies.get_mut(skill) {
+                        m! { *s = ProficiencyType::Double }
                     }
-                    None => {}
                 }
             }
 
@@ -263,14 +255,11 @@ impl Class for Rogue {
         if level == 20 {
             i! {
                 c.moves <<= Move::Other {
-                    element: if self.luck {
-                        Element::Trigger {
-                            text: "**Stroke of Luck:** You can turn an attack miss into a hit, or you can treat a failed `d20` roll as a 20. Once you use this feature, you can't use it again until you finish a short or long rest.",
-                            event: Event::Other("rogue stroke of luck"),
-                            button: "Use"
-                        }
-                    } else {
-                        Element::Str("**Stroke of Luck:** You can turn a missed attack into a hit, or you can treat a failed `d20` roll as a 20. You can't use this feature again until you finish a short or long rest.")
+                    element: Element::Resource {
+                        text: "**Stroke of Luck:** You can turn an attack miss into a hit, or you can treat a failed `d20` roll as a 20. Once you use this feature, you can't use it again until you finish a short or long rest.",
+                        data: &mut self.stroke_of_luck,
+                        max: 1,
+                        recharge: Recharge::ShortRest
                     },
                     time: MoveTime::Other("When your attack misses a target within range, or you fail an ability check.")
                 };
@@ -288,11 +277,6 @@ impl Class for Rogue {
     }
 
     fn event(&mut self, e: &Event, level: u32, index: usize) {
-        match e {
-            Event::ShortRest | Event::LongRest => self.luck = true,
-            Event::Other("rogue stroke of luck") => self.luck = false,
-            _ => {}
-        }
         self.subclass.event(e, level, index);
     }
 
@@ -23,19 +23,13 @@ impl Race for VariantHuman {
         }
 
         for ability in &self.abilities {
-            match c.abilities.get_mut(*ability) {
-                Some(a) => {
-                    m! { *a += 1 }
-                }
-                None => {}
+            if let Some(a) = c.abilities.get_mut(*ability) {
+                m! { *a += 1 }
             }
         }
 
-        match c.skill_proficiencies.get_mut(self.skill) {
-            Some(s) => {
-                i! { *s = ProficiencyType::Single }
-            }
-            None => {}
+        if let Some(s) = c.skill_proficiencies.get_mut(self.skill) {
+            i! { *s = ProficiencyType::Single }
         }
 
         i! {
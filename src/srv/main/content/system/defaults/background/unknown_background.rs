@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownBackground;
+#[serde(transparent)]
+pub struct UnknownBackground {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl Background for UnknownBackground {
@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownClass;
+#[serde(transparent)]
+pub struct UnknownClass {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl Class for UnknownClass {
@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownEldritchInvocation;
+#[serde(transparent)]
+pub struct UnknownEldritchInvocation {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl EldritchInvocation for UnknownEldritchInvocation {
@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownFeat;
+#[serde(transparent)]
+pub struct UnknownFeat {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl Feat for UnknownFeat {
@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownHalflingSubrace;
+#[serde(transparent)]
+pub struct UnknownHalflingSubrace {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl HalflingSubrace for UnknownHalflingSubrace {
@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Deserialize, Serialize, Default)]
-pub struct UnknownItem;
+#[serde(transparent)]
+pub struct UnknownItem {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl Item for UnknownItem {
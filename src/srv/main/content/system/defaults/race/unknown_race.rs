@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownRace;
+#[serde(transparent)]
+pub struct UnknownRace {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl Race for UnknownRace {
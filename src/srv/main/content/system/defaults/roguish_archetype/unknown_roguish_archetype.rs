@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownRoguishArchetype;
+#[serde(transparent)]
+pub struct UnknownRoguishArchetype {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl RoguishArchetype for UnknownRoguishArchetype {
@@ -1,7 +1,10 @@
 crate::name!("Unknown");
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UnknownSpell {}
+#[serde(transparent)]
+pub struct UnknownSpell {
+    original: Option<serde_json::Value>
+}
 
 #[content]
 impl Spell for UnknownSpell {
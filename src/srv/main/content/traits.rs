@@ -2,10 +2,10 @@ use std::fmt::Debug;
 use proc_macros::dynamic_choose;
 use crate::ui::{Chooseable, Event};
 use crate::character::Character;
-use crate::misc::{CastingTime, Equipable, Rarity, Equipped};
+use crate::misc::{Armor, CastingTime, Equipable, Rarity, Equipped};
 
 #[dynamic_choose]
-pub trait Race: Debug {
+pub trait Race: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -15,7 +15,7 @@ pub trait Race: Debug {
 }
 
 #[dynamic_choose]
-pub trait Class: Debug {
+pub trait Class: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -27,7 +27,7 @@ pub trait Class: Debug {
 }
 
 #[dynamic_choose]
-pub trait Feat: Debug {
+pub trait Feat: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -37,7 +37,7 @@ pub trait Feat: Debug {
 }
 
 #[dynamic_choose]
-pub trait Background: Debug {
+pub trait Background: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -47,7 +47,7 @@ pub trait Background: Debug {
 }
 
 #[dynamic_choose]
-pub trait Spell: Debug {
+pub trait Spell: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -68,7 +68,7 @@ pub trait Spell: Debug {
 /// - automatically add the ability score (other content might change which score is used)
 /// - check for proficiency and add the proficiency bonus (the common rules will do that).
 #[dynamic_choose]
-pub trait Item: Debug {
+pub trait Item: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -77,6 +77,8 @@ pub trait Item: Debug {
     fn rarity(&self) -> Rarity { Rarity::Common }
     fn weight(&self) -> Option<u32> { None }
     fn cost(&self) -> Option<u32> { None }
+    /// What the item does as armor or a shield, if it is one.
+    fn armor(&self) -> Option<Armor> { None }
 
     fn magical(&self) -> bool { false }
     fn attunable(&self) -> bool { false }
@@ -87,7 +89,7 @@ pub trait Item: Debug {
 }
 
 #[dynamic_choose]
-pub trait HalflingSubrace: Debug {
+pub trait HalflingSubrace: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -97,7 +99,7 @@ pub trait HalflingSubrace: Debug {
 }
 
 #[dynamic_choose]
-pub trait RoguishArchetype: Debug {
+pub trait RoguishArchetype: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -107,7 +109,7 @@ pub trait RoguishArchetype: Debug {
 }
 
 #[dynamic_choose]
-pub trait EldritchInvocation: Debug {
+pub trait EldritchInvocation: Debug + Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
     fn description_no_title(&self) -> &'static str;
@@ -0,0 +1,173 @@
+//! Server-sent events, so every page showing a character stays in sync with edits made elsewhere.
+//!
+//! Each open `/character/<id>/events` request gets an [EventStream], which Rocket streams to the
+//! browser's `EventSource` as it is read. The stream blocks on a channel until the character is
+//! published again, and holds on to one of Rocket's worker threads the whole time. A [Limit]
+//! keeps the streams from taking every worker, so the rest of the API still gets through.
+
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+/// How often to send something on a quiet stream. Writing is the only way to notice a closed
+/// tab, and it keeps proxies from timing out.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Everyone listening for changes to one character.
+#[derive(Default)]
+pub struct Events {
+    subscribers: Mutex<Vec<Sender<Arc<String>>>>
+}
+
+impl Events {
+    /// Opens a new stream, which holds on to `slot` until it closes. `first` is sent right away,
+    /// so the page starts out in sync.
+    pub fn subscribe(&self, event: &str, first: &str, slot: Slot) -> EventStream {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(Arc::new(message(event, first))).expect("could not send first event");
+        self.subscribers.lock().expect("could not get subscribers lock").push(sender);
+        EventStream {
+            _slot: slot,
+            receiver,
+            pending: Arc::new(String::new()),
+            position: 0,
+            flush: false
+        }
+    }
+
+    /// Sends an event to every open stream, and forgets the ones that have closed.
+    pub fn publish(&self, event: &str, data: &str) {
+        let message = Arc::new(message(event, data));
+        self.subscribers.lock().expect("could not get subscribers lock")
+            .retain(|s| s.send(message.clone()).is_ok());
+    }
+}
+
+/// The most streams that can be open at once, across every character.
+pub struct Limit {
+    open: Arc<AtomicUsize>,
+    max: usize
+}
+
+impl Limit {
+    pub fn new(max: usize) -> Self {
+        Limit {
+            open: Arc::new(AtomicUsize::new(0)),
+            max
+        }
+    }
+
+    /// Takes a slot for a new stream, or `None` if they are all taken.
+    pub fn reserve(&self) -> Option<Slot> {
+        let max = self.max;
+        self.open.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+            if open < max { Some(open + 1) } else { None }
+        }).ok()
+            .map(|_| Slot(self.open.clone()))
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+/// One open stream's place in a [Limit]. It is given back when dropped.
+pub struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Formats an event in the `text/event-stream` format.
+fn message(event: &str, data: &str) -> String {
+    let mut message = format!("event: {}\n", event);
+    for line in data.lines() {
+        message.push_str("data: ");
+        message.push_str(line);
+        message.push('\n');
+    }
+    message.push('\n');
+    message
+}
+
+pub struct EventStream {
+    _slot: Slot,
+    receiver: Receiver<Arc<String>>,
+    pending: Arc<String>,
+    position: usize,
+    flush: bool
+}
+
+impl Read for EventStream {
+    /// Blocks until there is an event, then hands it out. Once an event is fully read, the next
+    /// read returns `WouldBlock`, which tells Rocket to flush it to the browser.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.pending.len() {
+            if self.flush {
+                self.flush = false;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.pending = match self.receiver.recv_timeout(KEEP_ALIVE) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => Arc::new(": keep-alive\n\n".to_string()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0)
+            };
+            self.position = 0;
+        }
+        let bytes = &self.pending.as_bytes()[self.position..];
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        self.position += n;
+        self.flush = self.position == self.pending.len();
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_published_events() {
+        let events = Events::default();
+        let limit = Limit::new(1);
+        let mut stream = events.subscribe("character", "{\"name\":\"Jeebus\"}", limit.reserve().unwrap());
+        events.publish("character", "{\"name\":\n\"Creebus\"}");
+
+        let mut read = String::new();
+        let mut buf = [0; 7];
+        for _ in 0..2 {
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(n) => read.push_str(std::str::from_utf8(&buf[..n]).unwrap()),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => panic!("{}", e)
+                }
+            }
+        }
+        assert_eq!(
+            read,
+            "event: character\ndata: {\"name\":\"Jeebus\"}\n\nevent: character\ndata: {\"name\":\ndata: \"Creebus\"}\n\n"
+        );
+
+        std::mem::drop(events);
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn slots_are_given_back() {
+        let limit = Limit::new(2);
+        let first = limit.reserve().unwrap();
+        let second = limit.reserve().unwrap();
+        assert!(limit.reserve().is_none());
+        std::mem::drop(first);
+        let third = limit.reserve().unwrap();
+        assert!(limit.reserve().is_none());
+        std::mem::drop((second, third));
+        assert!(limit.reserve().is_some());
+    }
+}
@@ -0,0 +1,100 @@
+//! Golden-file tests for character resolution.
+//!
+//! Every stored character in `tests/characters` (plus `test_character.json`) is resolved, and the
+//! serialized [FinalCharacter](crate::character::FinalCharacter) is compared against the file of
+//! the same name in `tests/snapshots`.
+//!
+//! After a content change that is *supposed* to change the output, bless the new snapshots and
+//! check the diff before committing:
+//!
+//! ```bash
+//! $ DNDCENT_BLESS=1 cargo test golden
+//! $ git diff tests/snapshots
+//! ```
+
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::character::StoredCharacter;
+
+const CHARACTERS: &str = "tests/characters";
+const SNAPSHOTS: &str = "tests/snapshots";
+
+fn characters() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(CHARACTERS)
+        .unwrap_or_else(|_| panic!("READING FAILED: {}", CHARACTERS))
+        .map(|entry| entry.expect("READING FAILED").path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths.push(PathBuf::from("test_character.json"));
+    paths
+}
+
+/// Collects the JSON paths where `expected` and `actual` differ.
+fn diff(path: &str, expected: &Value, actual: &Value, out: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            for (key, ev) in e {
+                match a.get(key) {
+                    Some(av) => diff(&format!("{}.{}", path, key), ev, av, out),
+                    None => out.push(format!("{}.{}: missing", path, key))
+                }
+            }
+            for key in a.keys().filter(|key| !e.contains_key(*key)) {
+                out.push(format!("{}.{}: unexpected", path, key));
+            }
+        }
+        (Value::Array(e), Value::Array(a)) if e.len() == a.len() => {
+            for (i, (ev, av)) in e.iter().zip(a).enumerate() {
+                diff(&format!("{}[{}]", path, i), ev, av, out);
+            }
+        }
+        (e, a) if e != a => out.push(format!("{}: expected {}, got {}", path, e, a)),
+        _ => {}
+    }
+}
+
+fn check(character: &Path, bless: bool) -> Result<(), String> {
+    let name = character.file_name().unwrap().to_string_lossy().to_string();
+    let snapshot = Path::new(SNAPSHOTS).join(&name);
+
+    let mut stored = StoredCharacter::read(&character.to_string_lossy());
+    let actual = match stored.resolve() {
+        Ok(final_char) => serde_json::to_value(&final_char).expect("SERIALIZATION FAILED"),
+        Err(report) => return Err(format!("{}: {}", name, report))
+    };
+
+    if bless {
+        let json = serde_json::to_string_pretty(&actual).expect("SERIALIZATION FAILED");
+        std::fs::write(&snapshot, json + "\n")
+            .unwrap_or_else(|_| panic!("WRITING FAILED: {}", snapshot.display()));
+        return Ok(());
+    }
+
+    let expected: Value = match std::fs::read_to_string(&snapshot) {
+        Ok(json) => serde_json::from_str(&json).expect("DESERIALIZATION FAILED"),
+        Err(_) => return Err(format!("{}: no snapshot, run with DNDCENT_BLESS=1 to create one", name))
+    };
+    let mut differences = vec! [];
+    diff("", &expected, &actual, &mut differences);
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{}:\n    {}", name, differences.join("\n    ")))
+    }
+}
+
+#[test]
+fn golden() {
+    let bless = std::env::var_os("DNDCENT_BLESS").is_some();
+    let failures: Vec<String> = characters().iter()
+        .filter_map(|character| check(character, bless).err())
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} characters don't match their snapshots:\n\n{}",
+        failures.len(),
+        characters().len(),
+        failures.join("\n\n")
+    );
+}
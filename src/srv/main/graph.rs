@@ -0,0 +1,168 @@
+//! The read/write dependency graph between content stages and [Character] fields.
+//!
+//! Every `i!`, `m!`, and `f!` statement is one stage. A stage writes the field on its left hand
+//! side, and reads every field its `?` carriers point at. The macros only know these as
+//! source text, so the graph is built from the addresses each stage recorded when it was
+//! registered.
+//!
+//! Export it with `dndcent --graph <file>` and render it with Graphviz:
+//!
+//! ```bash
+//! $ dndcent --graph jeebus-creebus.json | dot -Tsvg > graph.svg
+//! ```
+
+use serde::Serialize;
+use std::collections::HashMap;
+use crate::character::{Character, StageKind, Source};
+
+/// One `i!`, `m!`, or `f!` statement, as run against one field.
+///
+/// Statements in loops show up once per field they touched.
+#[derive(Debug, Serialize)]
+pub struct GraphStage {
+    pub id: u64,
+    pub kind: StageKind,
+    pub source: Source,
+    pub location: &'static str,
+    pub done: bool,
+
+    /// Field written, by name.
+    pub writes: String,
+    /// Fields read through `?` carriers, by name.
+    pub reads: Vec<String>,
+
+    /// The left hand side, as written in the macro.
+    pub writes_text: &'static str,
+    /// The `?` carriers, as written in the macro.
+    pub reads_text: &'static [&'static str],
+
+    #[serde(skip)]
+    pub(crate) address: usize,
+    #[serde(skip)]
+    pub(crate) who: u64
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyGraph {
+    pub fields: Vec<String>,
+    pub stages: Vec<GraphStage>
+}
+
+impl DependencyGraph {
+    pub fn from_character(c: &Character) -> DependencyGraph {
+        let mut fields = vec! [];
+        let mut names: HashMap<usize, usize> = HashMap::new();
+        c.visit_stages(&mut |field, view| {
+            names.insert(view.address, fields.len());
+            fields.push(field.to_string());
+        });
+
+        let mut stages = vec! [];
+        c.visit_stages(&mut |field, view| {
+            for (kind, who, entry) in view.entries() {
+                let mut reads: Vec<String> = vec! [];
+                for address in &entry.reads {
+                    if let Some(i) = names.get(address) {
+                        if !reads.contains(&fields[*i]) {
+                            reads.push(fields[*i].clone());
+                        }
+                    }
+                }
+                stages.push(GraphStage {
+                    id: entry.info.id,
+                    kind,
+                    source: entry.source.clone(),
+                    location: entry.info.location,
+                    done: entry.done,
+                    writes: field.to_string(),
+                    reads,
+                    writes_text: entry.info.writes,
+                    reads_text: entry.info.reads,
+                    address: view.address,
+                    who
+                });
+            }
+        });
+        stages.sort_by_key(|s| (names[&s.address], s.kind as u8, s.id, s.who));
+
+        DependencyGraph {
+            fields,
+            stages
+        }
+    }
+
+    /// Renders the graph in Graphviz's DOT language.
+    ///
+    /// Fields are boxes, stages are ellipses grouped by the content that registered them.
+    /// Stages that never ran are red. Fields that no stage touches are left out.
+    pub fn to_dot(&self) -> String {
+        let mut used: Vec<&String> = vec! [];
+        for stage in &self.stages {
+            for field in std::iter::once(&stage.writes).chain(stage.reads.iter()) {
+                if !used.contains(&field) {
+                    used.push(field);
+                }
+            }
+        }
+
+        let mut clusters: Vec<(&Source, Vec<usize>)> = vec! [];
+        for (i, stage) in self.stages.iter().enumerate() {
+            match clusters.iter_mut().find(|(source, _)| **source == stage.source) {
+                Some((_, members)) => members.push(i),
+                None => clusters.push((&stage.source, vec! [i]))
+            }
+        }
+
+        let mut dot = String::from("digraph dndcent {\n    rankdir=LR;\n    node [shape=box];\n\n");
+        for field in &self.fields {
+            if used.contains(&field) {
+                dot += &format!("    \"{}\";\n", field);
+            }
+        }
+        for (n, (source, members)) in clusters.iter().enumerate() {
+            dot += &format!("\n    subgraph cluster_{} {{\n        label=\"{}\";\n", n, escape(&source.to_string()));
+            for i in members {
+                let stage = &self.stages[*i];
+                dot += &format!(
+                    "        \"stage {}\" [shape=ellipse, label=\"{} #{}\\n{}\", tooltip=\"{}\"{}];\n",
+                    i,
+                    macro_name(stage.kind),
+                    stage.id,
+                    escape(stage.location),
+                    escape(&tooltip(stage)),
+                    if stage.done { "" } else { ", color=red" }
+                );
+            }
+            dot += "    }\n";
+        }
+        dot += "\n";
+        for (i, stage) in self.stages.iter().enumerate() {
+            for read in &stage.reads {
+                dot += &format!("    \"{}\" -> \"stage {}\";\n", read, i);
+            }
+            dot += &format!("    \"stage {}\" -> \"{}\";\n", i, stage.writes);
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+fn macro_name(kind: StageKind) -> &'static str {
+    match kind {
+        StageKind::Initialize => "i!",
+        StageKind::Modify => "m!",
+        StageKind::Finalize => "f!"
+    }
+}
+
+fn tooltip(stage: &GraphStage) -> String {
+    if stage.reads_text.is_empty() {
+        stage.writes_text.to_string()
+    } else {
+        format!("{} <- {}", stage.writes_text, stage.reads_text.join(", "))
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
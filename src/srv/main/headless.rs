@@ -0,0 +1,114 @@
+//! Editing character files from the command line, without starting the server.
+//!
+//! Every edit does the same thing as an [EditRequest] sent to `/character/<id>/edit`, so a
+//! character can be set up from a script instead of by writing its JSON by hand:
+//!
+//! ```sh
+//! dndcent edit --new jeebus.json race Halfling add-level Rogue choose race:halfling/subrace 0 Stout
+//! ```
+//!
+//! The edits are made in order, and the file is only saved if all of them go through. Each one
+//! is recorded in the undo history, like an edit made in the interface.
+
+use serde_json::Value;
+use crate::character::StoredCharacter;
+use crate::history::History;
+use crate::server::{self, EditError, EditRequest};
+use crate::roster;
+
+#[derive(Debug)]
+pub enum Edit {
+    /// An [EditRequest] in the same JSON the server takes, like `{"health": 7}`.
+    Json(String),
+    /// Adds a level of the class, or the class at level 1 if the character doesn't have it yet.
+    AddLevel(String)
+}
+
+impl Edit {
+    fn apply(&self, stored_char: &mut StoredCharacter) -> Result<(), EditError> {
+        let request = match self {
+            Edit::Json(json) => serde_json::from_str(json)
+                .map_err(|e| EditError::InvalidEdit { reason: format!("{}: {}", json, e) })?,
+            Edit::AddLevel(name) => match stored_char.classes.iter().position(|(class, _)| class.name() == name) {
+                Some(index) => EditRequest::Level {
+                    index,
+                    level: stored_char.classes[index].1 + 1
+                },
+                None => EditRequest::Class {
+                    index: stored_char.classes.len(),
+                    name
+                }
+            }
+        };
+        server::apply_edit(request, stored_char)
+    }
+}
+
+/// Makes `edits` to the character file at `path`, and returns the character like the server
+/// sends it. With `new`, the edits start from a default character, and the file is only
+/// created once they all go through.
+pub(crate) fn edit(path: &str, edits: &[Edit], keep_backups: usize, new: bool) -> Result<Value, EditError> {
+    let (mut stored_char, mut history) = if new {
+        (StoredCharacter::default(), History::default())
+    } else {
+        (StoredCharacter::read(path), History::read(path))
+    };
+    let mut final_char = stored_char.resolve().map_err(|report| EditError::ResolutionDeadlock { report })?;
+    for edit in edits {
+        let before = serde_json::to_value(&stored_char).expect("SERIALIZATION FAILED");
+        edit.apply(&mut stored_char)?;
+        final_char = stored_char.resolve().map_err(|report| EditError::ResolutionDeadlock { report })?;
+        history.record(before);
+        history.next_revision();
+    }
+    roster::save(path, keep_backups, &stored_char, &history)?;
+    Ok(server::document(&final_char, history.revision()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn edits_are_saved_together() {
+        let dir = std::env::temp_dir().join(format!("dndcent-headless-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jeebus.json").to_string_lossy().to_string();
+
+        assert!(matches!(
+            edit(&path, &[Edit::Json(json!({ "name": "Jeebus" }).to_string()), Edit::Json("{\"nonsense\": 1}".to_string())], 0, true),
+            Err(EditError::InvalidEdit { .. })
+        ));
+        assert!(!std::path::Path::new(&path).exists());
+
+        let character = edit(&path, &[
+            Edit::Json(json!({ "name": "Jeebus" }).to_string()),
+            Edit::Json(json!({ "race": "Halfling" }).to_string()),
+            Edit::AddLevel("Rogue".to_string()),
+            Edit::AddLevel("Rogue".to_string()),
+            Edit::Json(json!({ "choice": { "element": "race:halfling/subrace", "choice_index": 0, "choice": "Stout" } }).to_string()),
+            Edit::Json(json!({ "ability_score": ["Strength", 14] }).to_string())
+        ], 0, true).unwrap();
+        assert_eq!(character["name"], json!("Jeebus"));
+        assert_eq!(character["class_levels"], json!([2]));
+        assert_eq!(character["abilities"]["strength"], json!(14));
+        assert_eq!(character["revision"], json!(6));
+
+        let saved = StoredCharacter::read(&path);
+        assert_eq!(saved.name, "Jeebus");
+        assert_eq!(History::read(&path).revision(), 6);
+
+        assert!(matches!(
+            edit(&path, &[Edit::Json(json!({ "name": "Creebus" }).to_string()), Edit::Json("{\"nonsense\": 1}".to_string())], 0, false),
+            Err(EditError::InvalidEdit { .. })
+        ));
+        assert!(matches!(
+            edit(&path, &[Edit::Json(json!({ "level": { "index": 0, "level": 21 } }).to_string())], 0, false),
+            Err(EditError::LevelOutOfRange { level: 21 })
+        ));
+        assert_eq!(StoredCharacter::read(&path).name, "Jeebus");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! Undo and redo for character edits.
+//!
+//! Every successful edit records a snapshot of the stored character from before the edit.
+//! Undoing swaps the current character for the newest snapshot and keeps the current one for
+//! redo. The history is saved to `<file>.history` next to the character file, so it survives
+//! restarts.
+//!
+//! The history also keeps the character's revision, which counts every change. Clients send
+//! back the revision they last saw with an edit, so an edit made against an out of date sheet
+//! can be turned down instead of changing the wrong thing.
+
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::backup;
+
+/// How many edits can be undone.
+pub const LIMIT: usize = 50;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    revision: u64,
+    undo: VecDeque<Value>,
+    redo: Vec<Value>
+}
+
+impl History {
+    pub fn path_for(character_path: &str) -> String {
+        format!("{}.history", character_path)
+    }
+
+    /// Loads the history of the character at `character_path`. A missing or unreadable history
+    /// just means there is nothing to undo.
+    pub fn read(character_path: &str) -> History {
+        let path = History::path_for(character_path);
+        let json = match std::fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(_) => return History::default()
+        };
+        serde_json::from_str(&json).unwrap_or_else(|e| {
+            eprintln!("WARNING: could not read undo history {}, starting a new one: {}", path, e);
+            History::default()
+        })
+    }
+
+    pub fn write(&self, character_path: &str) -> std::io::Result<()> {
+        let json = serde_json::to_string(self).expect("SERIALIZATION FAILED");
+        backup::write_atomic(&History::path_for(character_path), &json)
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Call once for every edit, undo or redo that goes through.
+    pub fn next_revision(&mut self) {
+        self.revision += 1;
+    }
+
+    /// Records the character as it was before an edit. Clears anything that could be redone.
+    pub fn record(&mut self, before: Value) {
+        self.undo.push_back(before);
+        while self.undo.len() > LIMIT {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    /// Returns the snapshot to go back to, and keeps `current` for redo.
+    pub fn undo(&mut self, current: Value) -> Option<Value> {
+        let previous = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Returns the snapshot that was last undone, and keeps `current` for undo.
+    ///
+    /// Also puts things back after an [undo](History::undo) that couldn't be used.
+    pub fn redo(&mut self, current: Value) -> Option<Value> {
+        let next = self.redo.pop()?;
+        self.undo.push_back(current);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::default();
+        assert_eq!(history.undo(json!(0)), None);
+        history.record(json!(0));
+        history.record(json!(1));
+
+        assert_eq!(history.undo(json!(2)), Some(json!(1)));
+        assert_eq!(history.undo(json!(1)), Some(json!(0)));
+        assert_eq!(history.undo(json!(0)), None);
+        assert_eq!(history.redo(json!(0)), Some(json!(1)));
+        assert_eq!(history.redo(json!(1)), Some(json!(2)));
+        assert_eq!(history.redo(json!(2)), None);
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let mut history = History::default();
+        history.record(json!(0));
+        assert_eq!(history.undo(json!(1)), Some(json!(0)));
+        history.record(json!(0));
+        assert_eq!(history.redo(json!(2)), None);
+        assert_eq!(history.undo(json!(2)), Some(json!(0)));
+    }
+
+    #[test]
+    fn only_the_newest_edits_are_kept() {
+        let mut history = History::default();
+        for i in 0..LIMIT + 10 {
+            history.record(json!(i));
+        }
+        let mut undone = vec! [];
+        while let Some(snapshot) = history.undo(json!(null)) {
+            undone.push(snapshot);
+        }
+        assert_eq!(undone.len(), LIMIT);
+        assert_eq!(undone.first(), Some(&json!(LIMIT + 9)));
+        assert_eq!(undone.last(), Some(&json!(10)));
+    }
+
+    #[test]
+    fn unusable_snapshots_are_put_back() {
+        // What travel does when the snapshot an undo lands on can't be loaded.
+        let mut history = History::default();
+        history.record(json!(0));
+        history.record(json!("unusable"));
+        let snapshot = history.undo(json!(2)).unwrap();
+        history.redo(snapshot);
+
+        assert_eq!(history.redo(json!(2)), None);
+        assert_eq!(history.undo(json!(2)), Some(json!("unusable")));
+        assert_eq!(history.undo(json!("unusable")), Some(json!(0)));
+    }
+}
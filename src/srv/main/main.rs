@@ -5,8 +5,8 @@
 //!
 //! The general process of serving the content is as follows:
 //!
-//! 1. Startup and load the character file given as command line arg.
-//! 2. Open a browser to localhost.
+//! 1. Startup and load the character files (or directory of them) given as command line args.
+//! 2. Open a browser to the server, unless told not to (see [settings]).
 //! 3. Respond to requests.
 //!
 //! The server is responsible for resolving [StoredCharacters](StoredCharacter) into [Characters](character::Character).
@@ -14,14 +14,20 @@
 //! More deats can be found in the documentation for other files.
 
 #![feature(proc_macro_hygiene, decl_macro)]
-#[deny(missing_docs)]
+#![deny(missing_docs)]
 
 #[macro_use] extern crate rocket;
 
 extern crate proc_macros;
 
 use std::process::exit;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use crate::character::StoredCharacter;
+use crate::settings::{Settings, LogLevel};
+use crate::headless::Edit;
+use rocket::error::LaunchErrorKind;
 use colored::Colorize;
 
 mod server;
@@ -31,79 +37,345 @@ mod ui;
 mod moves;
 mod misc;
 mod macros;
+mod graph;
+mod migration;
+mod unknown;
+mod backup;
+mod history;
+mod roster;
+mod party;
+mod money;
+mod events;
+mod patch;
+mod settings;
+mod headless;
+#[cfg(test)]
+mod golden;
 
-fn main() {
+enum Command {
+    Serve,
+    New,
+    Graph,
+    ListBackups,
+    RestoreBackup(usize),
+    /// `dndcent edit`: make the edits to the file without serving it, creating it first if `new`.
+    Edit {
+        new: bool,
+        edits: Vec<Edit>
+    }
+}
 
-    let args: Vec<String> = std::env::args().collect();
+fn main() {
+    let mut command = Command::Serve;
+    let mut paths = vec! [];
+    let mut keep_backups = backup::DEFAULT_KEEP;
+    let mut config = None;
+    let mut address = None;
+    let mut port = None;
+    let mut log_level = None;
+    let mut open_browser = None;
 
-    let path = match args.len() {
-        1 if cfg!(debug_assertions) => {
-            "test_character.json".to_string()
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map_or(false, |arg| arg == "edit") {
+        args.next();
+        command = parse_edit_command(args, &mut paths, &mut keep_backups);
+    } else {
+        while let Some(arg) = args.next() {
+            match &*arg {
+                "--new" | "-n" => command = Command::New,
+                "--graph" | "-g" => command = Command::Graph,
+                "--list-backups" | "-l" => command = Command::ListBackups,
+                "--restore-backup" | "-r" => command = Command::RestoreBackup(parse_arg(args.next())),
+                "--keep-backups" | "-k" => keep_backups = parse_arg(args.next()),
+                "--config" | "-c" => config = Some(parse_arg::<String>(args.next())),
+                "--address" | "-a" => address = Some(parse_arg(args.next())),
+                "--port" | "-p" => port = Some(parse_arg(args.next())),
+                "--log-level" => log_level = Some(parse_arg::<LogLevel>(args.next())),
+                "--browser" => open_browser = Some(true),
+                "--no-browser" => open_browser = Some(false),
+                _ if !arg.starts_with('-') => paths.push(arg),
+                _ => display_help()
+            }
         }
-        2 if args[1] != "--help" => {
-            args[1].clone()
+    }
+    if paths.is_empty() {
+        // Never edit the test character by accident.
+        if cfg!(debug_assertions) && !matches!(command, Command::Edit { .. }) {
+            paths.push("test_character.json".to_string());
+        } else {
+            display_help()
         }
-        3 => {
-            let path =
-                match &*args[1] {
-                    "--new" | "-n" => args[2].clone(),
-                    _ => match &*args[2] {
-                        "--new" | "-n" => args[1].clone(),
-                        _ => display_help()
-                    }
-                };
-            if std::path::Path::new(&*path).exists() {
-                println!("{}",
-                         format!("File {} already exists.\nPlease delete it before creating a new character file with that name.", path)
-                             .bright_red()
+    }
+    // Only serving works with more than one file.
+    if !matches!(command, Command::Serve) && paths.len() != 1 {
+        display_help()
+    }
+    let path = paths[0].clone();
+
+    if matches!(command, Command::New | Command::Edit { new: true, .. }) && Path::new(&*path).exists() {
+        println!("{}",
+                 format!("File {} already exists.\nPlease delete it before creating a new character file with that name.", path)
+                     .bright_red()
+        );
+        exit(2);
+    }
+    if matches!(command, Command::New) {
+        StoredCharacter::default().write(&*path)
+            .unwrap_or_else(|e| fail(format!("Could not write {}: {}", path, e)));
+    }
+    // Backups of deleted characters can still be listed and restored, and `edit --new` writes
+    // its file only after the edits.
+    if !matches!(command, Command::ListBackups | Command::RestoreBackup(_) | Command::Edit { new: true, .. }) {
+        for path in &paths {
+            if !Path::new(&**path).exists() {
+                println!(
+                    "{}",
+                    format!("File {} does not exist.\nPlease check that you gave the right path, or create it with -n or --new.", path)
+                        .bright_red()
                 );
-                exit(2);
+                exit(1);
+            }
+        }
+    }
+
+    match command {
+        Command::Serve | Command::New => {
+            let (files, directory) = if paths.len() == 1 && Path::new(&*path).is_dir() {
+                let files = roster::character_files(&*path)
+                    .unwrap_or_else(|e| fail(format!("Could not read directory {}: {}", path, e)));
+                (files, PathBuf::from(&path))
+            } else {
+                let directory = match Path::new(&*path).parent() {
+                    Some(p) if p != Path::new("") => p.to_path_buf(),
+                    _ => PathBuf::from(".")
+                };
+                (paths, directory)
+            };
+            let mut settings = match config {
+                Some(config) => Settings::read(&*config).unwrap_or_else(|e| fail(e)),
+                None => Settings::default()
+            };
+            settings.address = address.unwrap_or(settings.address);
+            settings.port = port.unwrap_or(settings.port);
+            settings.log_level = log_level.unwrap_or(settings.log_level);
+            settings.open_browser = open_browser.unwrap_or(settings.open_browser);
+
+            // Find out the port is taken before announcing the server or opening a browser to it.
+            if let Err(e) = TcpListener::bind((&*settings.address, settings.port)) {
+                fail(bind_failed(&settings, e))
+            }
+            let rocket = server::ignite(&files, directory, keep_backups, &settings).unwrap_or_else(|e| fail(e));
+            let error = rocket.launch();
+            match error.kind() {
+                LaunchErrorKind::Bind(e) => fail(bind_failed(&settings, e)),
+                _ => fail(format!("Could not start the server: {}", error))
+            }
+        }
+        Command::Edit { new, edits } => {
+            match headless::edit(&*path, &edits, keep_backups, new) {
+                Ok(character) => println!("{}", serde_json::to_string_pretty(&character).expect("SERIALIZATION FAILED")),
+                Err(e) => {
+                    println!("{}", serde_json::to_string_pretty(&e).expect("SERIALIZATION FAILED"));
+                    exit(1)
+                }
+            }
+        }
+        Command::Graph => {
+            let mut stored = StoredCharacter::read(&*path);
+            print!("{}", stored.dependency_graph().to_dot());
+        }
+        Command::ListBackups => {
+            let backups = backup::list(&*path).unwrap_or_else(|e| fail(format!("Could not list backups of {}: {}", path, e)));
+            if backups.is_empty() {
+                println!("There are no backups of {}.", path);
+            }
+            for (i, b) in backups.iter().enumerate() {
+                println!("{:>3}  {}  {}", i + 1, b.time().green(), b.path.display());
+            }
+        }
+        Command::RestoreBackup(n) => {
+            let backups = backup::list(&*path).unwrap_or_else(|e| fail(format!("Could not list backups of {}: {}", path, e)));
+            let chosen = match n.checked_sub(1).and_then(|i| backups.get(i)) {
+                Some(b) => b,
+                None => fail(format!("There is no backup number {} of {}. Use --list-backups to see them.", n, path))
+            };
+            backup::restore(&*path, chosen, keep_backups)
+                .unwrap_or_else(|e| fail(format!("Could not restore {}: {}", chosen.path.display(), e)));
+            println!("Restored {} from {}.", path.green(), chosen.time().green());
+        }
+    }
+}
+
+/// Reads the rest of `dndcent edit [-n] [-k <n>] <file> <edit>...`. The file goes in `paths`.
+fn parse_edit_command(mut args: impl Iterator<Item = String>, paths: &mut Vec<String>, keep_backups: &mut usize) -> Command {
+    let mut new = false;
+    let mut edits = vec! [];
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--new" | "-n" => new = true,
+            "--keep-backups" | "-k" => *keep_backups = parse_arg(args.next()),
+            _ if arg.starts_with('-') => display_help(),
+            _ if paths.is_empty() => paths.push(arg),
+            "name" => edits.push(edit_json("name", parse_arg::<String>(args.next()))),
+            "race" => edits.push(edit_json("race", parse_arg::<String>(args.next()))),
+            "background" => edits.push(edit_json("background", parse_arg::<String>(args.next()))),
+            "add-level" => edits.push(Edit::AddLevel(parse_arg(args.next()))),
+            "ability" => {
+                let ability = capitalize(&parse_arg::<String>(args.next()));
+                edits.push(edit_json("ability_score", (ability, parse_arg::<u32>(args.next()))));
+            }
+            "choose" => {
+                let element = parse_arg::<String>(args.next());
+                let choice_index = parse_arg::<usize>(args.next());
+                let choice = parse_arg::<String>(args.next());
+                edits.push(edit_json("choice", serde_json::json!({
+                    "element": element,
+                    "choice_index": choice_index,
+                    "choice": choice
+                })));
             }
-            StoredCharacter::default().write(&*path);
-            path
+            "rest" => edits.push(edit_json("event", match &*parse_arg::<String>(args.next()) {
+                "short" => "ShortRest",
+                "long" => "LongRest",
+                _ => display_help()
+            })),
+            "json" => edits.push(Edit::Json(parse_arg(args.next()))),
+            _ => display_help()
         }
+    }
+    if edits.is_empty() && !new {
+        display_help()
+    }
+    Command::Edit { new, edits }
+}
+
+fn parse_arg<T: FromStr>(arg: Option<String>) -> T {
+    match arg.map(|a| a.parse()) {
+        Some(Ok(n)) => n,
         _ => display_help()
-    };
-    if !std::path::Path::new(&*path).exists() {
-        println!(
-            "{}",
-            format!("File {} does not exist.\nPlease check that you gave the right path, or create it with -n or --new.", path)
-                .bright_red()
-        );
-        exit(1);
     }
-    let rocket = server::ignite(path.clone());
-    if cfg!(not(debug_assertions)) {
-        println!("Serving {} on {}", path.green(), "http://localhost:8000".green());
+}
+
+/// An edit of one [EditRequest](server::EditRequest) variant, like `{"name": "Jeebus"}`.
+fn edit_json(variant: &str, value: impl serde::Serialize) -> Edit {
+    let mut json = serde_json::Map::new();
+    json.insert(variant.to_string(), serde_json::to_value(value).expect("SERIALIZATION FAILED"));
+    Edit::Json(serde_json::Value::Object(json).to_string())
+}
+
+/// `strength` to `Strength`, the way abilities are named in edits.
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+        None => String::new()
     }
-    rocket.launch();
+}
+
+fn bind_failed(settings: &Settings, e: impl std::fmt::Display) -> String {
+    format!(
+        "Could not listen on {}:{}: {}\nIs something else (maybe another dndcent) using that port? Pick another one with --port.",
+        settings.address, settings.port, e
+    )
+}
+
+fn fail(message: String) -> ! {
+    println!("{}", message.bright_red());
+    exit(1)
 }
 
 fn display_help() -> ! {
     println!(
-        indoc::indoc! { r"
+        indoc::indoc! { r#"
             {} {} command line help
 
-            This is the DnDCent server. It either loads or creates a character file,
-            and serves the inteface on {}.
+            This is the DnDCent server. It either loads or creates character files,
+            and serves the inteface on {} (unless told otherwise).
+
+            USAGE: dndcent [OPTIONS] <file>...
+                   dndcent [OPTIONS] <directory>
+
+                <file>:       name of character file, should have .json extension (not required)
+                              The filename has no relation to the character's name.
+                              Several files can be served at once; every other option takes
+                              exactly one.
+                <directory>:  serve every .json file in the directory. New characters made
+                              from the interface are saved there (or next to the first file).
+
+            OPTIONS:
+
+                -n, --new:                  create a new default character. (fails if file already exists.)
+                -g, --graph:                print the character's stage dependency graph in Graphviz DOT format,
+                                            then exit without serving.
+                -k, --keep-backups <n>:     number of timestamped backups to keep next to the character
+                                            file. A new one is made every time the character is saved.
+                                            (default {}, 0 disables backups)
+                -c, --config <file>:        read server settings from a TOML file. Flags override it.
+                                            It can set address, port, log-level and open-browser,
+                                            and max-subscribers: how many pages can follow changes
+                                            at once. (default 48)
+                -a, --address <address>:    address to serve on. (default localhost; use 0.0.0.0 to
+                                            serve to the whole network)
+                -p, --port <port>:          port to serve on. (default 8000)
+                --log-level <level>:        off, critical, normal or debug. (default off, or normal
+                                            in debug builds)
+                --browser, --no-browser:    whether to open a browser once the server is up.
+                                            (default --browser, or --no-browser in debug builds)
+                -l, --list-backups:         list the backups of the character file, newest first, then exit.
+                -r, --restore-backup <n>:   restore backup number <n> from --list-backups, then exit.
+                                            The current file is backed up first. Stop the server before
+                                            restoring, or it will overwrite the file again on the next edit.
+
+            EDITING:
 
-            USAGE: dndcent [--new | -n] <file>
+                USAGE: dndcent edit [-n] [-k <n>] <file> <edit>...
 
-                <file>:     name of character file, should have .json extension (not required)
-                            The filename has no relation to the character's name.
-                -n, --new:  create a new default character. (fails if file already exists.)
+                Edits the character file without serving it, then prints the character as JSON.
+                The edits are made in order. If any of them fails, the error is printed as JSON
+                and the file is left alone. Stop the server first, like with --restore-backup.
+
+                -n, --new:                  start from a new default character. (fails if the file already exists.)
+                -k, --keep-backups <n>:     same as when serving.
+
+                name <name>:                set the character's name.
+                race <race>:                choose a race, like Halfling.
+                background <background>:    choose a background, like Soldier.
+                add-level <class>:          add a level of a class, or the class at level 1.
+                ability <ability> <n>:      set a base ability score, like `ability strength 14`.
+                choose <id> <i> <choice>:   make choice number <i> of the element with the given id.
+                                            Ids are in the "id" field of the printed character.
+                rest <short|long>:          take a short or long rest.
+                json <json>:                any edit the server accepts, like '{{"health": 7}}'.
 
             EXAMPLES:
 
                 # load and run and already existing character file:
                 dndcent jeebus-creebus.json
 
+                # run the whole party from one server:
+                dndcent party/
+
+                # serve to the LAN on another port, without opening a browser:
+                dndcent --address 0.0.0.0 --port 8080 --no-browser party/
+
                 # create and run a new character file:
                 dndcent --new jeebus-creebus.json
-        "},
+
+                # create a character from a script:
+                dndcent edit --new jeebus-creebus.json name Jeebus race Halfling add-level Rogue \
+                    choose race:halfling/subrace 0 Stout
+
+                # render the dependency graph of a character file:
+                dndcent --graph jeebus-creebus.json | dot -Tsvg > graph.svg
+
+                # undo the most recent save, or bring back a deleted character:
+                dndcent --list-backups jeebus-creebus.json
+                dndcent --restore-backup 1 jeebus-creebus.json
+        "#},
         "DnDCent".bright_green(),
         env!("CARGO_PKG_VERSION").green(),
-        "http://localhost:8000".bright_blue()
+        "http://localhost:8000".bright_blue(),
+        backup::DEFAULT_KEEP
     );
     exit(0)
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+//! Upgrades stored character files written by older versions of DnDCent.
+//!
+//! Every file has a `schema_version` (files from before versioning are version 0). When a change
+//! to [StoredCharacter](crate::character::StoredCharacter) or to content would break existing
+//! files, bump [CURRENT_VERSION] and add a function to [MIGRATIONS] that rewrites the JSON from
+//! the previous version. Migrations run in order on the raw JSON before it is deserialized, so
+//! they never need to know about the old Rust types.
+//!
+//! Renaming a content struct changes its typetag key; use [rename_content] for that.
+
+use serde_json::{Map, Value};
+use std::fmt::{Display, Formatter};
+
+pub const CURRENT_VERSION: u64 = 4;
+
+/// `MIGRATIONS[n]` upgrades a document from version `n` to version `n + 1`.
+const MIGRATIONS: [fn(&mut Map<String, Value>); CURRENT_VERSION as usize] = [
+    v0_to_v1,
+    v1_to_v2,
+    v2_to_v3,
+    v3_to_v4
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    NotAnObject,
+    BadVersion(Value),
+    TooNew {
+        version: u64
+    }
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::NotAnObject => write!(f, "a character file must be a JSON object"),
+            MigrationError::BadVersion(v) => write!(f, "schema_version must be a non-negative integer, found {}", v),
+            MigrationError::TooNew { version } => write!(
+                f,
+                "the file has schema version {}, but this version of DnDCent only understands up to {}. Please update DnDCent.",
+                version, CURRENT_VERSION
+            )
+        }
+    }
+}
+
+/// Brings a stored character document up to [CURRENT_VERSION].
+pub fn migrate(mut json: Value) -> Result<Value, MigrationError> {
+    let object = json.as_object_mut().ok_or(MigrationError::NotAnObject)?;
+    let version = match object.get("schema_version") {
+        None => 0,
+        Some(v) => v.as_u64().ok_or_else(|| MigrationError::BadVersion(v.clone()))?
+    };
+    if version > CURRENT_VERSION {
+        return Err(MigrationError::TooNew { version });
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(object);
+        object.insert("schema_version".to_string(), Value::from(from as u64 + 1));
+    }
+    Ok(json)
+}
+
+/// Version 1 added the `schema_version` field, and nothing else.
+fn v0_to_v1(_: &mut Map<String, Value>) {}
+
+/// Version 2 made the rogue's Stroke of Luck a resource, which counts the uses spent instead of
+/// whether it can be used.
+fn v1_to_v2(json: &mut Map<String, Value>) {
+    let classes = match json.get_mut("classes") {
+        Some(Value::Array(classes)) => classes,
+        _ => return
+    };
+    for class in classes {
+        if let Some(Value::Object(rogue)) = class.pointer_mut("/0/Rogue") {
+            if let Some(luck) = rogue.remove("luck") {
+                let spent = if luck == Value::Bool(false) { 1 } else { 0 };
+                rogue.insert("stroke_of_luck".to_string(), Value::from(spent));
+            }
+        }
+    }
+}
+
+/// Version 3 added the choice of the variant encumbrance rule, which is off unless asked for.
+fn v2_to_v3(json: &mut Map<String, Value>) {
+    json.insert("variant_encumbrance".to_string(), Value::Bool(false));
+}
+
+/// Version 4 added the ledger of money received and spent, which starts out empty.
+fn v3_to_v4(json: &mut Map<String, Value>) {
+    json.insert("ledger".to_string(), Value::Array(vec! []));
+}
+
+/// Renames every typetag key `from` to `to`, anywhere in the document.
+///
+/// Content is stored externally tagged, like `{"Alert": null}`, so this renames any single-key
+/// object whose key is `from`.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn rename_content(json: &mut Value, from: &str, to: &str) {
+    match json {
+        Value::Object(object) => {
+            if object.len() == 1 {
+                if let Some(inner) = object.remove(from) {
+                    object.insert(to.to_string(), inner);
+                }
+            }
+            for value in object.values_mut() {
+                rename_content(value, from, to);
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                rename_content(value, from, to);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::character::StoredCharacter;
+
+    fn version_0() -> Value {
+        let json = std::fs::read_to_string("test_character.json").unwrap();
+        let mut json: Value = serde_json::from_str(&json).unwrap();
+        json.as_object_mut().unwrap().remove("schema_version");
+        json
+    }
+
+    #[test]
+    fn v0_to_v1() {
+        let old = version_0();
+        let mut new = old.clone();
+        MIGRATIONS[0](new.as_object_mut().unwrap());
+        assert_eq!(new, old);
+    }
+
+    #[test]
+    fn v1_to_v2() {
+        let mut old = version_0();
+        old["schema_version"] = json!(1);
+        assert_eq!(old["classes"][0][0]["Rogue"]["luck"], json!(true));
+        let mut new = migrate(old).unwrap();
+
+        assert_eq!(new["schema_version"], json!(CURRENT_VERSION));
+        let rogue = &new["classes"][0][0]["Rogue"];
+        assert_eq!(rogue["stroke_of_luck"], json!(0));
+        assert!(rogue.get("luck").is_none());
+
+        new["classes"][0][0]["Rogue"]["luck"] = json!(false);
+        new["schema_version"] = json!(1);
+        let new = migrate(new).unwrap();
+        assert_eq!(new["classes"][0][0]["Rogue"]["stroke_of_luck"], json!(1));
+
+        let _: StoredCharacter = serde_json::from_value(new).unwrap();
+    }
+
+    #[test]
+    fn v2_to_v3() {
+        let mut old = migrate(version_0()).unwrap();
+        old.as_object_mut().unwrap().remove("variant_encumbrance");
+        old.as_object_mut().unwrap().remove("ledger");
+        old["schema_version"] = json!(2);
+        let new = migrate(old).unwrap();
+        assert_eq!(new["variant_encumbrance"], json!(false));
+        let _: StoredCharacter = serde_json::from_value(new).unwrap();
+    }
+
+    #[test]
+    fn v3_to_v4() {
+        let mut old = migrate(version_0()).unwrap();
+        old.as_object_mut().unwrap().remove("ledger");
+        old["schema_version"] = json!(3);
+        let new = migrate(old).unwrap();
+        assert_eq!(new["ledger"], json!([]));
+        let _: StoredCharacter = serde_json::from_value(new).unwrap();
+    }
+
+    #[test]
+    fn current_is_unchanged() {
+        let current = migrate(version_0()).unwrap();
+        assert_eq!(migrate(current.clone()).unwrap(), current);
+    }
+
+    #[test]
+    fn saved_characters_are_current() {
+        let saved = serde_json::to_value(StoredCharacter::default()).unwrap();
+        assert_eq!(saved["schema_version"], json!(CURRENT_VERSION));
+    }
+
+    #[test]
+    fn rejects_newer_and_malformed_files() {
+        let mut newer = version_0();
+        newer["schema_version"] = json!(CURRENT_VERSION + 1);
+        assert!(matches!(migrate(newer), Err(MigrationError::TooNew { .. })));
+
+        let mut bad = version_0();
+        bad["schema_version"] = json!("one");
+        assert!(matches!(migrate(bad), Err(MigrationError::BadVersion(_))));
+
+        assert!(matches!(migrate(json!([])), Err(MigrationError::NotAnObject)));
+    }
+
+    #[test]
+    fn rename_content_renames_nested_tags() {
+        let mut json = json!({
+            "race": { "VariantHuman": { "feat": { "Alert": null } } },
+            "inventory": [[{ "Alert": null }, "No", false]],
+            "description": { "Alert": 1, "other": 2 }
+        });
+        rename_content(&mut json, "Alert", "Alertness");
+        assert_eq!(json, json!({
+            "race": { "VariantHuman": { "feat": { "Alertness": null } } },
+            "inventory": [[{ "Alertness": null }, "No", false]],
+            "description": { "Alert": 1, "other": 2 }
+        }));
+    }
+}
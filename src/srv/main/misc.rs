@@ -138,6 +138,31 @@ pub enum Equipable {
     Holdable(Holdable)
 }
 
+impl Equipable {
+    /// Whether an item like this can be in the `equipped` state.
+    pub fn allows(self, equipped: Equipped) -> bool {
+        match (self, equipped) {
+            (_, Equipped::No) => self != Equipable::Always,
+            (Equipable::Yes, Equipped::Yes)
+            | (Equipable::Always, Equipped::Yes)
+            | (Equipable::Armor, Equipped::Yes)
+            | (Equipable::Holdable(Holdable::Ammunition), Equipped::Yes) => true,
+            (Equipable::Holdable(Holdable::One), Equipped::Held(hand)) => hand != Hand::Both,
+            (Equipable::Holdable(Holdable::Two), Equipped::Held(hand)) => hand == Hand::Both,
+            (Equipable::Holdable(Holdable::Versatile), Equipped::Held(_)) => true,
+            _ => false
+        }
+    }
+
+    /// How a newly added item starts out.
+    pub fn default_equipped(self) -> Equipped {
+        match self {
+            Equipable::Always => Equipped::Yes,
+            _ => Equipped::No
+        }
+    }
+}
+
 /// What type of holdable item it is.
 ///
 /// - **One**: always held in one hand
@@ -164,6 +189,17 @@ pub enum Equipped {
     Held(Hand)
 }
 
+/// An item in the character's inventory, as the frontend sees it. Inventory edits refer to
+/// items by their index in this list.
+#[derive(Debug, Serialize, Clone)]
+pub struct InventoryItem {
+    pub name: &'static str,
+    pub equipped: Equipped,
+    pub attuned: bool,
+    pub equipable: Equipable,
+    pub attunable: bool
+}
+
 /// Which hand a versatile item is held in.
 #[derive(Debug, Deserialize, Serialize, Copy, Clone, Eq, PartialEq)]
 pub enum Hand {
@@ -172,6 +208,39 @@ pub enum Hand {
     Both
 }
 
+/// Which row of the armor table a piece of armor is from.
+#[derive(Debug, Serialize, Copy, Clone, Eq, PartialEq)]
+pub enum ArmorCategory {
+    Light,
+    Medium,
+    Heavy,
+    Shield
+}
+
+/// What a piece of armor does when it's worn, or a shield when it's held.
+#[derive(Debug, Serialize, Copy, Clone, Eq, PartialEq)]
+pub struct Armor {
+    pub category: ArmorCategory,
+    /// The armor class it gives, or how much a shield adds to it.
+    pub base: u32,
+    /// The most of the Dexterity modifier that is added, or `None` for all of it.
+    pub dex_cap: Option<i32>,
+    /// The Strength score needed to wear it without being 10 feet slower.
+    pub strength: Option<u32>,
+    pub stealth_disadvantage: bool
+}
+
+impl Armor {
+    /// The armor class of someone wearing this, with the given Dexterity modifier.
+    pub fn armor_class(self, dexterity: i32) -> u32 {
+        let dexterity = match self.dex_cap {
+            Some(cap) => dexterity.min(cap),
+            None => dexterity
+        };
+        (self.base as i32 + dexterity).max(0) as u32
+    }
+}
+
 #[choose]
 pub enum Rarity {
     Common,
@@ -196,6 +265,66 @@ pub enum CreatureSize {
     Colossal
 }
 
+impl CreatureSize {
+    /// Scales a carrying capacity, or a push, drag, or lift limit, from a Medium creature's to
+    /// this size's. Each size bigger than Medium doubles it, and each size smaller than Small
+    /// halves it.
+    pub fn carrying(self, pounds: u32) -> u32 {
+        match self {
+            CreatureSize::Fine => pounds / 8,
+            CreatureSize::Diminutive => pounds / 4,
+            CreatureSize::Tiny => pounds / 2,
+            CreatureSize::Large => pounds * 2,
+            CreatureSize::Huge => pounds * 4,
+            CreatureSize::Gargantuan => pounds * 8,
+            CreatureSize::Colossal => pounds * 16,
+            _ => pounds
+        }
+    }
+}
+
+/// How weighed down the character is by what they carry.
+///
+/// - **Encumbered**: 10 feet slower. Only with the variant encumbrance rule.
+/// - **HeavilyEncumbered**: 20 feet slower, and disadvantage on ability checks and saving throws
+///   that use Strength, Dexterity, or Constitution. Only with the variant encumbrance rule.
+/// - **OverCapacity**: carrying more than their carrying capacity, so they can only push or
+///   drag it along at 5 feet.
+#[derive(Debug, Serialize, Copy, Clone, Eq, PartialEq)]
+pub enum Encumbrance {
+    Unencumbered,
+    Encumbered,
+    HeavilyEncumbered,
+    OverCapacity
+}
+
+impl Encumbrance {
+    /// `thresholds` are the weights where the variant rule makes the character encumbered and
+    /// heavily encumbered, if it's used.
+    pub fn of(weight: u32, capacity: u32, thresholds: Option<(u32, u32)>) -> Encumbrance {
+        match thresholds {
+            _ if weight > capacity => Encumbrance::OverCapacity,
+            Some((_, heavily)) if weight > heavily => Encumbrance::HeavilyEncumbered,
+            Some((encumbered, _)) if weight > encumbered => Encumbrance::Encumbered,
+            _ => Encumbrance::Unencumbered
+        }
+    }
+
+    /// The speed `speed` drops to.
+    pub fn speed(self, speed: u32) -> u32 {
+        match self {
+            Encumbrance::Unencumbered => speed,
+            Encumbrance::Encumbered => speed.saturating_sub(10),
+            Encumbrance::HeavilyEncumbered => speed.saturating_sub(20),
+            Encumbrance::OverCapacity => speed.min(5)
+        }
+    }
+}
+
+impl Default for Encumbrance {
+    fn default() -> Self { Encumbrance::Unencumbered }
+}
+
 #[choose]
 pub enum Alignment {
     LawfulGood = "Lawful Good",
@@ -389,6 +518,15 @@ impl Vantage {
     pub(crate) fn sub_assign<T>(&mut self, _other: T) {
         self.downgrade();
     }
+
+    /// This, downgraded if `disadvantage` is true. For stages that only sometimes give
+    /// disadvantage, since `-=` always does.
+    pub(crate) fn downgraded_if(mut self, disadvantage: bool) -> Vantage {
+        if disadvantage {
+            self.downgrade();
+        }
+        self
+    }
 }
 
 impl Default for Vantage {
@@ -425,6 +563,7 @@ pub enum GamingSet {
 }
 
 #[choose]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ASIOrFeat {
     ASI = "Ability Score Increase",
     Feat,
@@ -0,0 +1,171 @@
+//! Receiving and spending coins, and the ledger that records it.
+//!
+//! Amounts can be given in any kind of coin. Spending takes the smallest coins first, and when
+//! they don't add up to the price exactly, breaks the smallest coin that covers the rest and
+//! gives the change back in as few coins as possible. Prices from [Item::cost](crate::content::traits::Item::cost)
+//! are in gold pieces.
+
+use serde::{Deserialize, Serialize};
+use enum_iterator::IntoEnumIterator;
+use crate::misc::{MoneyType, MoneyTypeMap};
+use crate::server::EditError;
+
+/// One change to the character's coins.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Transaction {
+    /// Positive when money was received, negative when it was spent.
+    pub amount: i64,
+    pub money_type: MoneyType,
+    /// How many of each coin were gained or lost, after making change.
+    pub coins: MoneyTypeMap<i64>,
+    pub note: String
+}
+
+impl MoneyType {
+    /// How many copper pieces one of these coins is worth.
+    pub fn in_copper(self) -> u32 {
+        match self {
+            MoneyType::Platinum => 1000,
+            MoneyType::Gold => 100,
+            MoneyType::Electrum => 50,
+            MoneyType::Silver => 10,
+            MoneyType::Copper => 1,
+            MoneyType::Unknown => 0
+        }
+    }
+}
+
+/// Adds `amount` coins of `money_type` to the `purse`. The purse is left alone if it can't hold
+/// that many.
+pub(crate) fn receive(purse: &mut MoneyTypeMap<u32>, amount: u32, money_type: MoneyType, note: String) -> Result<Transaction, EditError> {
+    let coins = purse.get_mut(money_type).ok_or(EditError::UnknownMoneyType)?;
+    *coins = coins.checked_add(amount).ok_or(EditError::TooMuchMoney { money_type })?;
+    let mut change = MoneyTypeMap::default();
+    *change.get_mut_known(money_type) = amount as i64;
+    Ok(Transaction {
+        amount: amount as i64,
+        money_type,
+        coins: change,
+        note
+    })
+}
+
+/// Takes `amount` coins of `money_type` worth of money out of the `purse`, making change if it
+/// has to. The purse is left alone if there isn't enough in it.
+pub(crate) fn spend(purse: &mut MoneyTypeMap<u32>, amount: u32, money_type: MoneyType, note: String) -> Result<Transaction, EditError> {
+    if !money_type.known() {
+        return Err(EditError::UnknownMoneyType);
+    }
+    let needed = amount as u64 * money_type.in_copper() as u64;
+    let available = total(purse);
+    if available < needed {
+        return Err(EditError::InsufficientFunds { needed, available });
+    }
+
+    let mut change = MoneyTypeMap::default();
+    let mut left = needed;
+    for money_type in smallest_first() {
+        let value = money_type.in_copper() as u64;
+        // Less than the coins in the purse, so it fits in a u32.
+        let paid = (*purse.get_known(money_type) as u64).min(left / value) as u32;
+        *purse.get_mut_known(money_type) -= paid;
+        *change.get_mut_known(money_type) -= paid as i64;
+        left -= paid as u64 * value;
+    }
+    if left > 0 {
+        // Every coin still in the purse is worth more than what's left, or it would have been
+        // spent, and there's enough money in total, so there is one to break. The change is all
+        // in smaller coins, and the purse has none of those left, so it can't overflow.
+        let broken = smallest_first()
+            .find(|money_type| *purse.get_known(*money_type) > 0)
+            .expect("NO COIN TO BREAK");
+        *purse.get_mut_known(broken) -= 1;
+        *change.get_mut_known(broken) -= 1;
+        let mut owed = broken.in_copper() - left as u32;
+        for money_type in smallest_first().rev() {
+            let value = money_type.in_copper();
+            *purse.get_mut_known(money_type) += owed / value;
+            *change.get_mut_known(money_type) += (owed / value) as i64;
+            owed %= value;
+        }
+    }
+    Ok(Transaction {
+        amount: -(amount as i64),
+        money_type,
+        coins: change,
+        note
+    })
+}
+
+/// Everything in the `purse`, in copper pieces.
+pub fn total(purse: &MoneyTypeMap<u32>) -> u64 {
+    MoneyType::into_enum_iter()
+        .filter(|money_type| money_type.known())
+        .map(|money_type| *purse.get_known(money_type) as u64 * money_type.in_copper() as u64)
+        .sum()
+}
+
+fn smallest_first() -> impl DoubleEndedIterator<Item = MoneyType> {
+    vec! [MoneyType::Copper, MoneyType::Silver, MoneyType::Electrum, MoneyType::Gold, MoneyType::Platinum].into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn purse(platinum: u32, gold: u32, electrum: u32, silver: u32, copper: u32) -> MoneyTypeMap<u32> {
+        MoneyTypeMap { platinum, gold, electrum, silver, copper }
+    }
+
+    fn coins(purse: &MoneyTypeMap<u32>) -> [u32; 5] {
+        [purse.platinum, purse.gold, purse.electrum, purse.silver, purse.copper]
+    }
+
+    #[test]
+    fn spending_makes_change() {
+        let mut p = purse(0, 3, 0, 2, 5);
+        spend(&mut p, 25, MoneyType::Copper, String::new()).unwrap();
+        assert_eq!(coins(&p), [0, 3, 0, 0, 0]);
+
+        let t = spend(&mut p, 1, MoneyType::Silver, "Candle".to_string()).unwrap();
+        assert_eq!(coins(&p), [0, 2, 1, 4, 0]);
+        assert_eq!((t.coins.gold, t.coins.electrum, t.coins.silver), (-1, 1, 4));
+        assert_eq!(t.amount, -1);
+
+        let mut p = purse(1, 0, 0, 0, 0);
+        spend(&mut p, 3, MoneyType::Gold, String::new()).unwrap();
+        assert_eq!(coins(&p), [0, 7, 0, 0, 0]);
+        assert_eq!(total(&p), 700);
+    }
+
+    #[test]
+    fn overspending_leaves_the_purse_alone() {
+        let mut p = purse(0, 1, 0, 9, 9);
+        assert!(matches!(
+            spend(&mut p, 2, MoneyType::Gold, String::new()),
+            Err(EditError::InsufficientFunds { needed: 200, available: 199 })
+        ));
+        assert_eq!(coins(&p), [0, 1, 0, 9, 9]);
+
+        receive(&mut p, 1, MoneyType::Copper, String::new()).unwrap();
+        spend(&mut p, 2, MoneyType::Gold, String::new()).unwrap();
+        assert_eq!(coins(&p), [0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn big_purses_dont_overflow() {
+        let mut p = purse(0, 5_000_000, 0, 0, 0);
+        assert_eq!(total(&p), 500_000_000);
+        spend(&mut p, 4_999_999, MoneyType::Gold, String::new()).unwrap();
+        assert_eq!(coins(&p), [0, 1, 0, 0, 0]);
+
+        let mut p = purse(u32::MAX, 0, 0, 0, u32::MAX);
+        spend(&mut p, u32::MAX, MoneyType::Platinum, String::new()).unwrap();
+        assert_eq!(coins(&p), [4_294_967, 2, 1, 4, 5]);
+        assert!(matches!(
+            receive(&mut p, u32::MAX, MoneyType::Platinum, String::new()),
+            Err(EditError::TooMuchMoney { money_type: MoneyType::Platinum })
+        ));
+        assert_eq!(coins(&p), [4_294_967, 2, 1, 4, 5]);
+    }
+}
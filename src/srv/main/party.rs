@@ -0,0 +1,162 @@
+//! Named groups of characters, and the DM's read-only summary of them.
+//!
+//! Parties are saved to `parties.toml` in the roster's directory, so they can also be written by
+//! hand:
+//!
+//! ```toml
+//! "Tuesday group" = ["jeebus-creebus", "sir-reginald"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::misc::{Language, PassiveSkillMap};
+use crate::roster::Roster;
+use crate::server::EditError;
+use crate::backup;
+
+pub const FILE: &str = "parties.toml";
+
+pub struct Parties {
+    path: PathBuf,
+    parties: BTreeMap<String, Vec<String>>
+}
+
+/// One party member, as the DM sees them.
+#[derive(Debug, Serialize)]
+pub struct MemberSummary {
+    pub id: String,
+    pub name: String,
+    pub classes: Vec<(String, u32)>,
+    pub armor_class: u32,
+    pub health: u32,
+    pub temp_health: u32,
+    pub max_health: u32,
+    pub passives: PassiveSkillMap<i32>,
+    pub conditions: Vec<&'static str>,
+    pub languages: Vec<Language>
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartySummary {
+    pub name: String,
+    pub members: Vec<MemberSummary>,
+    /// Members that aren't being served (anymore).
+    pub missing: Vec<String>
+}
+
+impl Parties {
+    /// Loads the parties saved in `directory`. A missing file means there are no parties yet.
+    pub fn read(directory: &Path) -> Result<Parties, String> {
+        let path = directory.join(FILE);
+        let parties = match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("Could not read {}: {}", path.display(), e))?,
+            Err(_) => BTreeMap::new()
+        };
+        Ok(Parties { path, parties })
+    }
+
+    pub fn list(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.parties
+    }
+
+    /// Creates the party `name`, or replaces its members. Every member must be a served character.
+    pub(crate) fn set(&mut self, name: &str, members: Vec<String>, roster: &Roster) -> Result<(), EditError> {
+        if name.trim().is_empty() {
+            return Err(EditError::InvalidPartyName { name: name.to_string() });
+        }
+        for id in &members {
+            roster.get(id)?;
+        }
+        let mut parties = self.parties.clone();
+        parties.insert(name.to_string(), members);
+        self.replace(parties)
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Result<(), EditError> {
+        let mut parties = self.parties.clone();
+        parties.remove(name).ok_or_else(|| EditError::UnknownParty { name: name.to_string() })?;
+        self.replace(parties)
+    }
+
+    pub(crate) fn summary(&self, name: &str, roster: &Roster) -> Result<PartySummary, EditError> {
+        let ids = self.parties.get(name).ok_or_else(|| EditError::UnknownParty { name: name.to_string() })?;
+        let mut members = vec! [];
+        let mut missing = vec! [];
+        for id in ids {
+            let sheet = match roster.get(id) {
+                Ok(sheet) => sheet,
+                Err(_) => {
+                    missing.push(id.clone());
+                    continue;
+                }
+            };
+            let c = sheet.final_char.read().expect("could not get party final read lock");
+            members.push(MemberSummary {
+                id: id.clone(),
+                name: c.name.clone(),
+                classes: c.class_names.iter().cloned().zip(c.class_levels.iter().copied()).collect(),
+                armor_class: c.armor_class,
+                health: c.health,
+                temp_health: c.temp_health,
+                max_health: c.max_health,
+                passives: c.passives.clone(),
+                conditions: c.conditions.clone(),
+                languages: c.languages.clone()
+            });
+        }
+        Ok(PartySummary {
+            name: name.to_string(),
+            members,
+            missing
+        })
+    }
+
+    /// Saves `parties`, and only then starts serving them, so what's served is what's on disk.
+    fn replace(&mut self, parties: BTreeMap<String, Vec<String>>) -> Result<(), EditError> {
+        let text = toml::to_string(&parties).expect("SERIALIZATION FAILED");
+        backup::write_atomic(&*self.path.to_string_lossy(), &text)
+            .map_err(|e| EditError::PartyFile { reason: format!("could not save {}: {}", self.path.display(), e) })?;
+        self.parties = parties;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parties_summarize_their_members() {
+        let dir = std::env::temp_dir().join(format!("dndcent-party-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("test_character.json", dir.join("rogue.json")).unwrap();
+        let roster = Roster::load(&[dir.join("rogue.json").to_string_lossy().to_string()], dir.clone(), 0);
+        let extra = roster.create(None).unwrap();
+
+        let mut parties = Parties::read(&dir).unwrap();
+        assert!(matches!(
+            parties.set("Tuesday", vec! ["rogue".to_string(), "nobody".to_string()], &roster),
+            Err(EditError::UnknownCharacter { .. })
+        ));
+        parties.set("Tuesday", vec! ["rogue".to_string(), extra.clone()], &roster).unwrap();
+        roster.delete(&extra).unwrap();
+
+        let summary = Parties::read(&dir).unwrap().summary("Tuesday", &roster).unwrap();
+        assert_eq!(summary.members.len(), 1);
+        assert_eq!(summary.members[0].classes, vec! [("Rogue".to_string(), 20)]);
+        assert_eq!(summary.members[0].armor_class, 17);
+        assert_eq!(summary.members[0].passives.perception, 22);
+        assert_eq!(summary.missing, vec! [extra]);
+
+        parties.remove("Tuesday").unwrap();
+        assert!(matches!(parties.summary("Tuesday", &roster), Err(EditError::UnknownParty { .. })));
+
+        // Parties that can't be saved aren't served either.
+        parties.set("Tuesday", vec! ["rogue".to_string()], &roster).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(parties.set("Friday", vec! ["rogue".to_string()], &roster), Err(EditError::PartyFile { .. })));
+        assert!(matches!(parties.remove("Tuesday"), Err(EditError::PartyFile { .. })));
+        assert_eq!(parties.list().keys().collect::<Vec<_>>(), vec! ["Tuesday"]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Structural diffs of JSON documents, in the [JSON Patch](https://tools.ietf.org/html/rfc6902)
+//! format.
+//!
+//! A serialized [FinalCharacter](crate::character::FinalCharacter) is big, mostly feature
+//! descriptions, and a typical edit changes a handful of numbers. So after an edit the server
+//! sends a patch from the old character to the new one instead of the whole thing.
+
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value }
+}
+
+/// The operations that turn `before` into `after`.
+///
+/// Objects are compared key by key and arrays index by index, with anything past the end of the
+/// shorter array added or removed. Array elements are removed from the back, so the indices stay
+/// right while the patch is applied in order.
+pub fn diff(before: &Value, after: &Value) -> Vec<Operation> {
+    let mut patch = vec! [];
+    diff_into("", before, after, &mut patch);
+    patch
+}
+
+fn diff_into(path: &str, before: &Value, after: &Value, patch: &mut Vec<Operation>) {
+    match (before, after) {
+        (Value::Object(b), Value::Object(a)) => {
+            for (key, bv) in b {
+                let path = format!("{}/{}", path, escape(key));
+                match a.get(key) {
+                    Some(av) => diff_into(&path, bv, av, patch),
+                    None => patch.push(Operation::Remove { path })
+                }
+            }
+            for (key, av) in a.iter().filter(|(key, _)| !b.contains_key(*key)) {
+                patch.push(Operation::Add {
+                    path: format!("{}/{}", path, escape(key)),
+                    value: av.clone()
+                });
+            }
+        }
+        (Value::Array(b), Value::Array(a)) => {
+            for (i, (bv, av)) in b.iter().zip(a).enumerate() {
+                diff_into(&format!("{}/{}", path, i), bv, av, patch);
+            }
+            for i in (a.len()..b.len()).rev() {
+                patch.push(Operation::Remove { path: format!("{}/{}", path, i) });
+            }
+            for (i, av) in a.iter().enumerate().skip(b.len()) {
+                patch.push(Operation::Add {
+                    path: format!("{}/{}", path, i),
+                    value: av.clone()
+                });
+            }
+        }
+        (b, a) if b != a => patch.push(Operation::Replace {
+            path: path.to_string(),
+            value: a.clone()
+        }),
+        _ => {}
+    }
+}
+
+/// Escapes a key for use in a JSON pointer.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Just enough of a JSON Patch implementation to check the diffs.
+    fn apply(doc: &mut Value, patch: &[Operation]) {
+        for op in patch {
+            let (path, value) = match op {
+                Operation::Add { path, value } | Operation::Replace { path, value } => (path, Some(value.clone())),
+                Operation::Remove { path } => (path, None)
+            };
+            if path.is_empty() {
+                *doc = value.unwrap();
+                continue;
+            }
+            let split = path.rfind('/').unwrap();
+            let parent = doc.pointer_mut(&path[..split]).unwrap();
+            let key = path[split + 1..].replace("~1", "/").replace("~0", "~");
+            match (parent, op) {
+                (Value::Array(a), Operation::Add { .. }) => a.insert(key.parse().unwrap(), value.unwrap()),
+                (Value::Array(a), Operation::Remove { .. }) => { a.remove(key.parse().unwrap()); }
+                (Value::Array(a), Operation::Replace { .. }) => a[key.parse::<usize>().unwrap()] = value.unwrap(),
+                (Value::Object(o), Operation::Remove { .. }) => { o.remove(&key); }
+                (Value::Object(o), _) => { o.insert(key, value.unwrap()); }
+                _ => panic!("bad path {}", path)
+            }
+        }
+    }
+
+    #[test]
+    fn diffs_only_what_changed() {
+        let before = json!({ "health": 3, "languages": ["Common"], "a/b": { "x": 1 }, "gone": null });
+        let after = json!({ "health": 7, "languages": ["Common", "Elvish"], "a/b": { "x": 1 }, "new": true });
+        assert_eq!(diff(&before, &after), vec! [
+            Operation::Remove { path: "/gone".to_string() },
+            Operation::Replace { path: "/health".to_string(), value: json!(7) },
+            Operation::Add { path: "/languages/1".to_string(), value: json!("Elvish") },
+            Operation::Add { path: "/new".to_string(), value: json!(true) }
+        ]);
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn patches_reproduce_characters() {
+        let characters: Vec<Value> = std::fs::read_dir("tests/snapshots").unwrap()
+            .map(|entry| serde_json::from_str(&std::fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap())
+            .collect();
+        for before in &characters {
+            for after in &characters {
+                let mut patched = before.clone();
+                apply(&mut patched, &diff(before, after));
+                assert_eq!(&patched, after);
+            }
+        }
+    }
+}
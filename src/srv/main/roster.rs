@@ -0,0 +1,267 @@
+//! All of the characters served by one server.
+//!
+//! The server is given either a directory or a list of character files. Each character gets an
+//! id from its file name (`jeebus-creebus.json` is `jeebus-creebus`), and is served under
+//! `/character/<id>`. New and duplicated characters are written to the directory, and deleted
+//! characters are backed up before their file is removed, so they can be restored with
+//! `--restore-backup`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+use colored::Colorize;
+use crate::character::{StoredCharacter, FinalCharacter, DeadlockReport};
+use crate::history::History;
+use crate::events::Events;
+use crate::server::EditError;
+use crate::backup;
+
+/// One character, and everything the server keeps about it.
+pub struct Sheet {
+    pub path: String,
+    pub stored_char: RwLock<StoredCharacter>,
+    pub final_char: RwLock<FinalCharacter>,
+    pub history: RwLock<History>,
+    pub events: Events,
+    /// Set by [Roster::delete] while it holds the `stored_char` write lock, so edits that got the
+    /// sheet before it was deleted don't write the file back.
+    deleted: AtomicBool
+}
+
+impl Sheet {
+    fn new(path: String, mut stored_char: StoredCharacter) -> Result<Sheet, DeadlockReport> {
+        let final_char = stored_char.resolve()?;
+        let history = History::read(&*path);
+        Ok(Sheet {
+            path,
+            stored_char: RwLock::new(stored_char),
+            final_char: RwLock::new(final_char),
+            history: RwLock::new(history),
+            events: Events::default(),
+            deleted: AtomicBool::new(false)
+        })
+    }
+
+    /// Fails if the character was deleted. Check it while holding the `stored_char` lock.
+    pub(crate) fn check_deleted(&self, id: &str) -> Result<(), EditError> {
+        if self.deleted.load(Ordering::SeqCst) {
+            Err(EditError::UnknownCharacter { id: id.to_string() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// What the character list shows for each character.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub id: String,
+    pub name: String,
+    pub path: String
+}
+
+pub struct Roster {
+    directory: PathBuf,
+    keep_backups: usize,
+    characters: RwLock<BTreeMap<String, Arc<Sheet>>>
+}
+
+impl Roster {
+    /// Loads and resolves every file in `paths`. New characters will be created in `directory`.
+    pub fn load(paths: &[String], directory: PathBuf, keep_backups: usize) -> Roster {
+        let mut characters = BTreeMap::new();
+        for path in paths {
+            let stored_char = StoredCharacter::read(&*path);
+            let sheet = Sheet::new(path.clone(), stored_char)
+                .unwrap_or_else(|report| {
+                    eprintln!("{}", report);
+                    panic!("ignite character resolve failed: {}", path)
+                });
+            let id = unique_id(&characters, &id_from_path(path));
+            characters.insert(id, Arc::new(sheet));
+        }
+        Roster {
+            directory,
+            keep_backups,
+            characters: RwLock::new(characters)
+        }
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Result<Arc<Sheet>, EditError> {
+        self.characters.read()
+            .expect("could not get roster read lock")
+            .get(id)
+            .cloned()
+            .ok_or_else(|| EditError::UnknownCharacter { id: id.to_string() })
+    }
+
+    pub fn list(&self) -> Vec<Summary> {
+        self.characters.read()
+            .expect("could not get roster read lock")
+            .iter()
+            .map(|(id, sheet)| Summary {
+                id: id.clone(),
+                name: sheet.stored_char.read().expect("could not get list stored read lock").name.clone(),
+                path: sheet.path.clone()
+            })
+            .collect()
+    }
+
+    /// Creates a new default character. Without an `id`, one is made up.
+    pub(crate) fn create(&self, id: Option<String>) -> Result<String, EditError> {
+        self.add(id, "character", StoredCharacter::default())
+    }
+
+    /// Copies the character `id` to a new file. Without a `new_id`, it is `<id>-copy`.
+    pub(crate) fn duplicate(&self, id: &str, new_id: Option<String>) -> Result<String, EditError> {
+        let sheet = self.get(id)?;
+        let json = serde_json::to_value(&*sheet.stored_char.read().expect("could not get duplicate stored read lock"))
+            .expect("SERIALIZATION FAILED");
+        let (copy, _) = StoredCharacter::from_json(json).expect("DESERIALIZATION FAILED");
+        self.add(new_id, &format!("{}-copy", id), copy)
+    }
+
+    /// Stops serving the character `id` and removes its file and history. A backup is always
+    /// kept, even if backups are turned off.
+    pub(crate) fn delete(&self, id: &str) -> Result<(), EditError> {
+        let mut characters = self.characters.write().expect("could not get roster write lock");
+        {
+            let sheet = characters.get(id).ok_or_else(|| EditError::UnknownCharacter { id: id.to_string() })?;
+            // Wait for anyone still editing it.
+            let _editing = sheet.stored_char.write().expect("could not get delete stored write lock");
+            backup::back_up(&*sheet.path, self.keep_backups.max(1))
+                .and_then(|_| std::fs::remove_file(&*sheet.path))
+                .map_err(|e| EditError::CharacterFile { reason: format!("could not delete {}: {}", sheet.path, e) })?;
+            let _ = std::fs::remove_file(History::path_for(&*sheet.path));
+            sheet.deleted.store(true, Ordering::SeqCst);
+        }
+        characters.remove(id);
+        Ok(())
+    }
+
+    pub(crate) fn save(&self, sheet: &Sheet, stored_char: &StoredCharacter, history: &History) -> Result<(), EditError> {
+        save(&*sheet.path, self.keep_backups, stored_char, history)
+    }
+
+    fn add(&self, id: Option<String>, default_id: &str, stored_char: StoredCharacter) -> Result<String, EditError> {
+        let mut characters = self.characters.write().expect("could not get roster write lock");
+        let id = match id {
+            Some(id) if !valid_id(&id) => return Err(EditError::InvalidCharacterId { id }),
+            Some(id) if characters.contains_key(&id) => return Err(EditError::CharacterExists { id }),
+            Some(id) => id,
+            None => unique_id(&characters, default_id)
+        };
+        let path = self.directory.join(format!("{}.json", id)).to_string_lossy().to_string();
+        if Path::new(&path).exists() {
+            return Err(EditError::CharacterExists { id });
+        }
+        let sheet = Sheet::new(path, stored_char).map_err(|report| EditError::ResolutionDeadlock { report })?;
+        sheet.stored_char.read().expect("could not get add stored read lock").write(&*sheet.path)
+            .map_err(|e| EditError::CharacterFile { reason: format!("could not write {}: {}", sheet.path, e) })?;
+        characters.insert(id.clone(), Arc::new(sheet));
+        Ok(id)
+    }
+}
+
+/// Backs up the character file, then writes the character and its history. Only failing to
+/// write the character itself is an error.
+pub(crate) fn save(path: &str, keep_backups: usize, stored_char: &StoredCharacter, history: &History) -> Result<(), EditError> {
+    if let Err(e) = backup::back_up(path, keep_backups) {
+        eprintln!("{}", format!("WARNING: could not back up {}: {}", path, e).yellow());
+    }
+    stored_char.write(path)
+        .map_err(|e| EditError::CharacterFile { reason: format!("could not write {}: {}", path, e) })?;
+    if let Err(e) = history.write(path) {
+        eprintln!("{}", format!("WARNING: could not save undo history of {}: {}", path, e).yellow());
+    }
+    Ok(())
+}
+
+/// The `.json` files in `directory`, sorted.
+pub fn character_files(directory: &str) -> std::io::Result<Vec<String>> {
+    let mut paths = vec! [];
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+            paths.push(path.to_string_lossy().to_string());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Ids go in urls and file names, so they are limited to letters, digits, `-` and `_`.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn id_from_path(path: &str) -> String {
+    let stem = Path::new(path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let id: String = stem.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .take(64)
+        .collect();
+    if id.is_empty() { "character".to_string() } else { id }
+}
+
+/// `id`, or `id-2`, `id-3`, ... if it's taken.
+fn unique_id<T>(taken: &BTreeMap<String, T>, id: &str) -> String {
+    if !taken.contains_key(id) {
+        return id.to_string();
+    }
+    (2..).map(|n| format!("{}-{}", id, n))
+        .find(|candidate| !taken.contains_key(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids() {
+        assert_eq!(id_from_path("dir/jeebus-creebus.json"), "jeebus-creebus");
+        assert_eq!(id_from_path("Sir Jeebus.json"), "Sir-Jeebus");
+        assert!(!valid_id("../etc/passwd"));
+        assert!(!valid_id(""));
+
+        let mut taken = BTreeMap::new();
+        taken.insert("a".to_string(), ());
+        taken.insert("a-2".to_string(), ());
+        assert_eq!(unique_id(&taken, "a"), "a-3");
+        assert_eq!(unique_id(&taken, "b"), "b");
+    }
+
+    #[test]
+    fn create_duplicate_and_delete() {
+        let dir = std::env::temp_dir().join(format!("dndcent-roster-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let roster = Roster::load(&[], dir.clone(), 0);
+
+        let id = roster.create(None).unwrap();
+        assert_eq!(id, "character");
+        assert!(dir.join("character.json").exists());
+        assert!(matches!(roster.create(Some(id.clone())), Err(EditError::CharacterExists { .. })));
+        assert!(matches!(roster.create(Some("a/b".to_string())), Err(EditError::InvalidCharacterId { .. })));
+
+        roster.get(&id).unwrap().stored_char.write().unwrap().name = "Jeebus".to_string();
+        let copy = roster.duplicate(&id, None).unwrap();
+        assert_eq!(copy, "character-copy");
+        let names: Vec<(String, String)> = roster.list().into_iter().map(|s| (s.id, s.name)).collect();
+        assert_eq!(names, vec! [
+            ("character".to_string(), "Jeebus".to_string()),
+            ("character-copy".to_string(), "Jeebus".to_string())
+        ]);
+
+        let editing = roster.get(&id).unwrap();
+        roster.delete(&id).unwrap();
+        assert!(matches!(editing.check_deleted(&id), Err(EditError::UnknownCharacter { .. })));
+        assert!(!dir.join("character.json").exists());
+        assert_eq!(backup::list(&*dir.join("character.json").to_string_lossy()).unwrap().len(), 1);
+        assert!(matches!(roster.get(&id), Err(EditError::UnknownCharacter { .. })));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,46 +1,86 @@
-use rocket::{Rocket, State, config::{Environment, Config}};
+use rocket::{Rocket, State, Request, Response, config::{Environment, Config}};
+use std::path::PathBuf;
+use std::convert::TryFrom;
 use std::sync::RwLock;
-use crate::character::{StoredCharacter,FinalCharacter};
-use rocket::response::content;
+use std::cmp::Ordering;
+use crate::character::{StoredCharacter, FinalCharacter, DeadlockReport};
+use rocket::response::{self, content, Responder};
+use rocket::http::{Status, ContentType};
 use rocket_contrib::json::Json;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use rocket_contrib::serve::StaticFiles;
-use rocket::config::LoggingLevel;
-use crate::misc::Ability;
-use crate::ui::{Element, Event};
-use crate::moves::Move;
+use rocket::fairing::AdHoc;
+use crate::misc::{Ability, Equipable, Equipped, MoneyType};
+use crate::money;
+use crate::content::traits::Item;
+use crate::content::common::common_item_rules::{self, InventoryProblem};
+use crate::ui::{Event, ChoiceError, ElementEdit, ElementEditError, ResourceChange};
 use crate::content::Registration;
+use crate::history::History;
+use crate::roster::{Roster, Sheet};
+use crate::party::Parties;
+use crate::patch::{self, Operation};
+use crate::settings::{self, Settings};
+use crate::events::Limit;
+use colored::Colorize;
+use serde_json::Value;
 
 struct SharedData {
-    path: String,
-    stored_char: RwLock<StoredCharacter>,
-    final_char: RwLock<FinalCharacter>
+    roster: Roster,
+    parties: RwLock<Parties>,
+    subscribers: Limit
 }
 
-pub(crate) fn ignite(path: String) -> Rocket {
+/// Workers left for everything but event streams.
+const RESERVED_WORKERS: usize = 16;
+
+/// Sets up the server for the characters in `paths`. Fails if the settings don't make sense to Rocket.
+pub(crate) fn ignite(paths: &[String], directory: PathBuf, keep_backups: usize, settings: &Settings) -> Result<Rocket, String> {
     let dev = cfg!(debug_assertions);
+    let workers = u16::try_from(settings.max_subscribers + RESERVED_WORKERS)
+        .map_err(|_| format!("Invalid server settings: max-subscribers can be at most {}", u16::MAX as usize - RESERVED_WORKERS))?;
     let config = Config::build(Environment::active()
                                .expect("active rocket env not found"))
-        .address("localhost")
-        .port(8000)
-        .log_level(if cfg!(debug_assertions) {LoggingLevel::Normal} else {LoggingLevel::Off})
+        .address(&*settings.address)
+        .port(settings.port)
+        .log_level(settings.log_level.into())
+        // Every open event stream holds on to a worker, so there need to be plenty.
+        .workers(workers)
         .secret_key(make_secret_key())
         .finalize()
-        .expect("failed to config rocket");
-    let mut stored_char = StoredCharacter::read(&*path);
-    let final_char = stored_char.resolve().expect("ignite character resolve failed");
+        .map_err(|e| format!("Invalid server settings: {}", e))?;
+    let parties = Parties::read(&directory)?;
     let state = SharedData {
-        path,
-        stored_char: RwLock::new(stored_char),
-        final_char: RwLock::new(final_char)
+        roster: Roster::load(paths, directory, keep_backups),
+        parties: RwLock::new(parties),
+        subscribers: Limit::new(settings.max_subscribers)
     };
+    let url = settings.url();
+    let open_browser = settings.open_browser;
+    let announce: Vec<String> = paths.to_vec();
     let rocket = rocket::custom(config)
         .manage(state)
-        .mount("/", routes![get_character, edit_character, serve_registry, serve_description]);
-    return if dev {
-        rocket.mount("/", StaticFiles::from("src/www/build"))
+        .attach(AdHoc::on_launch("Announce", move |_| {
+            if !dev {
+                for path in &announce {
+                    println!("Serving {}", path.green());
+                }
+                println!("on {}", url.green());
+            }
+            if open_browser {
+                settings::open_browser(&url);
+            }
+        }))
+        .mount("/", routes![
+            list_characters, create_character, duplicate_character, delete_character,
+            get_character, get_breakdown, character_events, edit_character, undo, redo,
+            list_parties, set_party, delete_party, get_party,
+            serve_registry, serve_description
+        ]);
+    if dev {
+        Ok(rocket.mount("/", StaticFiles::from("src/www/build")))
     } else {
-        rocket.mount("/", routes![serve_root, serve_static_file])
+        Ok(rocket.mount("/", routes![serve_root, serve_static_file]))
     }
 }
 
@@ -53,31 +93,170 @@ fn make_secret_key() -> String {
     base64::encode(&bytes)
 }
 
-#[post("/")]
-fn get_character(state: State<SharedData>) -> content::Json<String> {
-    let final_char = state.inner().final_char.read()
+#[post("/characters")]
+fn list_characters(state: State<SharedData>) -> content::Json<String> {
+    content::Json(serde_json::to_string(&state.inner().roster.list()).expect("SERIALIZATION FAILED"))
+}
+
+/// Body of the create and duplicate requests. Without an id, the server picks one.
+#[derive(Deserialize, Debug)]
+struct NewCharacter {
+    #[serde(default)]
+    id: Option<String>
+}
+
+#[post("/characters/new", format="json", data="<data>")]
+fn create_character(data: Json<NewCharacter>, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    state.inner().roster.create(data.into_inner().id)?;
+    Ok(list_characters(state))
+}
+
+#[post("/character/<id>/duplicate", format="json", data="<data>")]
+fn duplicate_character(id: String, data: Json<NewCharacter>, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    state.inner().roster.duplicate(&id, data.into_inner().id)?;
+    Ok(list_characters(state))
+}
+
+#[post("/character/<id>/delete")]
+fn delete_character(id: String, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    state.inner().roster.delete(&id)?;
+    Ok(list_characters(state))
+}
+
+#[post("/character/<id>")]
+fn get_character(id: String, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    let sheet = state.inner().roster.get(&id)?;
+    Ok(render(&sheet))
+}
+
+/// Where every number on the sheet came from. See [Breakdown](crate::character::Breakdown).
+#[post("/character/<id>/breakdown")]
+fn get_breakdown(id: String, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    let sheet = state.inner().roster.get(&id)?;
+    let final_char = sheet.final_char.read()
+        .expect("could not get breakdown final read lock");
+    Ok(content::Json(serde_json::to_string(&final_char.breakdown).expect("SERIALIZATION FAILED")))
+}
+
+fn render(sheet: &Sheet) -> content::Json<String> {
+    let final_char = sheet.final_char.read()
         .expect("could not get get final read lock");
-    content::Json(serde_json::to_string(&*final_char).expect("SERIALIZATION FAILED"))
+    let history = sheet.history.read()
+        .expect("could not get get history read lock");
+    content::Json(document(&*final_char, history.revision()).to_string())
+}
+
+/// The character as it's sent to clients: the final character, plus its revision.
+pub(crate) fn document(final_char: &FinalCharacter, revision: u64) -> Value {
+    let mut document = serde_json::to_value(final_char).expect("SERIALIZATION FAILED");
+    document["revision"] = Value::from(revision);
+    document
+}
+
+/// A `text/event-stream` that sends the whole character right away as a `character` event, then
+/// a `patch` event every time it changes.
+#[get("/character/<id>/events")]
+fn character_events(id: String, state: State<SharedData>) -> Result<Response<'static>, EditError> {
+    let sheet = state.inner().roster.get(&id)?;
+    let slot = state.inner().subscribers.reserve()
+        .ok_or(EditError::TooManySubscribers { max: state.inner().subscribers.max() })?;
+    // Changes are published while the final character is write locked, so holding the read lock
+    // means no patch is missed or sent twice.
+    let final_char = sheet.final_char.read()
+        .expect("could not get events final read lock");
+    let history = sheet.history.read()
+        .expect("could not get events history read lock");
+    let json = document(&*final_char, history.revision()).to_string();
+    let stream = sheet.events.subscribe("character", &json, slot);
+    Ok(Response::build()
+        .header(ContentType::new("text", "event-stream"))
+        .raw_header("Cache-Control", "no-cache")
+        .chunked_body(stream, 4096)
+        .finalize())
+}
+
+/// Sends everyone listening the patch from `before` to `after`, and returns what the editor
+/// asked for: the same patch, or with `full`, the whole character.
+fn publish(sheet: &Sheet, before: &Value, after: Value, full: bool) -> content::Json<String> {
+    let document = serde_json::to_string(&after).expect("SERIALIZATION FAILED");
+    let mut patch = serde_json::to_string(&patch::diff(before, &after)).expect("SERIALIZATION FAILED");
+    // A patch that changes most of the character is no better than the character itself.
+    if patch.len() > document.len() {
+        let replace_all = [Operation::Replace { path: String::new(), value: after }];
+        patch = serde_json::to_string(&replace_all).expect("SERIALIZATION FAILED");
+    }
+    sheet.events.publish("patch", &patch);
+    content::Json(if full { document } else { patch })
+}
+
+#[post("/parties")]
+fn list_parties(state: State<SharedData>) -> content::Json<String> {
+    let parties = state.inner().parties.read().expect("could not get parties read lock");
+    content::Json(serde_json::to_string(parties.list()).expect("SERIALIZATION FAILED"))
+}
+
+#[derive(Deserialize, Debug)]
+struct PartyMembers {
+    members: Vec<String>
+}
+
+/// Creates the party, or replaces its members.
+#[post("/party/<name>/set", format="json", data="<data>")]
+fn set_party(name: String, data: Json<PartyMembers>, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    state.inner().parties.write().expect("could not get parties write lock")
+        .set(&name, data.into_inner().members, &state.inner().roster)?;
+    Ok(list_parties(state))
+}
+
+#[post("/party/<name>/delete")]
+fn delete_party(name: String, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    state.inner().parties.write().expect("could not get parties write lock").remove(&name)?;
+    Ok(list_parties(state))
+}
+
+/// The DM's read-only view of the party: a short summary of every member.
+#[post("/party/<name>")]
+fn get_party(name: String, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    let summary = state.inner().parties.read().expect("could not get parties read lock")
+        .summary(&name, &state.inner().roster)?;
+    Ok(content::Json(serde_json::to_string(&summary).expect("SERIALIZATION FAILED")))
+}
+
+/// An [EditRequest], plus the revision of the character it was made against, like
+/// `{"health": 7, "revision": 12}`. Without a revision, the edit is always applied.
+#[derive(Deserialize, Debug)]
+struct Edit<'a> {
+    #[serde(borrow, flatten)]
+    request: EditRequest<'a>,
+    #[serde(default)]
+    revision: Option<u64>
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
-enum EditRequest<'a> {
+pub(crate) enum EditRequest<'a> {
     Name(String),
     Description(String),
     Health(u32),
     TempHealth(u32),
+    /// Turns the variant encumbrance rule on or off.
+    VariantEncumbrance(bool),
+    /// `element` is the `id` of an [Element::Choice](crate::ui::Element::Choice) anywhere on the character.
     Choice {
-        container: Container,
-        element_index: usize,
+        element: String,
         choice_index: usize,
         choice: &'a str
     },
+    /// `element` is the `id` of an [Element::Toggle](crate::ui::Element::Toggle) anywhere on the character.
     Toggle {
-        container: Container,
-        element_index: usize,
+        element: String,
         toggle_index: usize
     },
+    /// `element` is the `id` of an [Element::Resource](crate::ui::Element::Resource). The change is to the uses left.
+    Resource {
+        element: String,
+        change: ResourceChange
+    },
     Event(Event<'a>),
     Race(&'a str),
     Class {
@@ -89,108 +268,392 @@ enum EditRequest<'a> {
         level: u32
     },
     Background(&'a str),
-    AbilityScore(Ability, u32)
+    AbilityScore(Ability, u32),
+    /// Adds an item to the end of the inventory.
+    AddItem(&'a str),
+    /// `index` is the item's place in the inventory, for this and the rest of the item edits.
+    RemoveItem {
+        index: usize
+    },
+    /// Equips, unequips, or holds an item, like `{"Held": "Right"}`.
+    Equip {
+        index: usize,
+        equipped: Equipped
+    },
+    Attune {
+        index: usize,
+        attuned: bool
+    },
+    /// Adds `amount` coins of `money_type`, like `{"receive": {"amount": 5, "money_type": "Gold"}}`.
+    Receive {
+        amount: u32,
+        money_type: MoneyType,
+        #[serde(default)]
+        note: String
+    },
+    /// Pays `amount` coins of `money_type` worth, making change from whatever coins there are.
+    Spend {
+        amount: u32,
+        money_type: MoneyType,
+        #[serde(default)]
+        note: String
+    },
+    /// Pays for an item at its [cost](crate::content::traits::Item::cost) and adds it to the inventory.
+    Buy {
+        name: &'a str,
+        #[serde(default)]
+        note: String
+    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "snake_case")]
-enum Container {
-    Race,
-    Class(usize),
-    Background,
-    Feat,
-    Moves
+/// Everything that can go wrong with an [EditRequest], an undo or redo, or managing the characters
+/// and parties.
+///
+/// Serialized to json and returned with the matching HTTP status. The stored character is
+/// never left half-edited when one of these is returned.
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub(crate) enum EditError {
+    UnknownRace { name: String },
+    UnknownClass { name: String },
+    UnknownBackground { name: String },
+    ClassIndexOutOfBounds { index: usize, len: usize },
+    LevelOutOfRange { level: u32 },
+    UnknownElement { id: String },
+    NotAChoice { id: String },
+    NotAToggle { id: String },
+    NotAResource { id: String },
+    ResourceOutOfRange { id: String, max: u32 },
+    /// An edit from the command line that isn't an [EditRequest].
+    InvalidEdit { reason: String },
+    InvalidChoice { reason: ChoiceError },
+    UnknownAbility,
+    ResolutionDeadlock { report: DeadlockReport },
+    NothingToUndo,
+    NothingToRedo,
+    UnusableHistory { reason: String },
+    UnknownCharacter { id: String },
+    InvalidCharacterId { id: String },
+    CharacterExists { id: String },
+    CharacterFile { reason: String },
+    UnknownParty { name: String },
+    InvalidPartyName { name: String },
+    PartyFile { reason: String },
+    StaleRevision { given: u64, current: u64 },
+    UnknownItem { name: String },
+    ItemIndexOutOfBounds { index: usize, len: usize },
+    CannotEquip { name: &'static str, equipable: Equipable, equipped: Equipped },
+    NotAttunable { name: &'static str },
+    /// The inventory edit would break the rules in a new way.
+    InvalidInventory { problems: Vec<InventoryProblem> },
+    UnknownMoneyType,
+    /// Both in copper pieces.
+    InsufficientFunds { needed: u64, available: u64 },
+    /// More coins of `money_type` than the character file can hold.
+    TooMuchMoney { money_type: MoneyType },
+    NotForSale { name: &'static str },
+    /// Every event stream the server allows is already open.
+    TooManySubscribers { max: usize }
+}
+
+impl EditError {
+    fn status(&self) -> Status {
+        match self {
+            EditError::UnknownRace { .. }
+            | EditError::UnknownClass { .. }
+            | EditError::UnknownBackground { .. }
+            | EditError::ClassIndexOutOfBounds { .. }
+            | EditError::UnknownElement { .. }
+            | EditError::UnknownCharacter { .. }
+            | EditError::UnknownParty { .. }
+            | EditError::UnknownItem { .. }
+            | EditError::ItemIndexOutOfBounds { .. } => Status::NotFound,
+            EditError::CharacterExists { .. }
+            | EditError::StaleRevision { .. } => Status::Conflict,
+            EditError::ResolutionDeadlock { .. }
+            | EditError::UnusableHistory { .. }
+            | EditError::CharacterFile { .. }
+            | EditError::PartyFile { .. } => Status::InternalServerError,
+            EditError::TooManySubscribers { .. } => Status::ServiceUnavailable,
+            _ => Status::UnprocessableEntity
+        }
+    }
+}
+
+impl<'r> Responder<'r> for EditError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let json = serde_json::to_string(&self).expect("SERIALIZATION FAILED");
+        Response::build_from(content::Json(json).respond_to(request)?)
+            .status(self.status())
+            .ok()
+    }
+}
+
+impl From<ChoiceError> for EditError {
+    fn from(reason: ChoiceError) -> Self {
+        EditError::InvalidChoice { reason }
+    }
 }
 
-#[post("/edit", format="json", data="<data>")]
-fn edit_character(data: Json<EditRequest>, state: State<SharedData>) -> content::Json<String> {
-    let mut final_char = state.inner().final_char.write()
+/// Returns a JSON patch from the old character to the new one, or the whole new character with
+/// `?full=true`.
+#[post("/character/<id>/edit?<full>", format="json", data="<data>")]
+fn edit_character(id: String, full: Option<bool>, data: Json<Edit>, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    let roster = &state.inner().roster;
+    let sheet = roster.get(&id)?;
+    let mut final_char = sheet.final_char.write()
         .expect("could not get edit final write lock");
-    let mut stored_char = state.inner().stored_char.write()
+    let mut stored_char = sheet.stored_char.write()
         .expect("could not get edit stored write lock");
+    sheet.check_deleted(&id)?;
+    let mut history = sheet.history.write()
+        .expect("could not get edit history write lock");
+
+    let Edit { request, revision } = data.into_inner();
+    check_revision(revision, &history)?;
+
+    // Edits are validated before they touch anything, but resolution or saving can still fail
+    // afterward. Keep a copy around so a bad edit can be rolled back, and so it can be undone.
+    let before = serde_json::to_value(&*stored_char).expect("SERIALIZATION FAILED");
+    let before_final = document(&*final_char, history.revision());
+    let before_history = history.clone();
+
+    apply_edit(request, &mut *stored_char)?;
+    let new_final = match stored_char.resolve() {
+        Ok(new_final) => new_final,
+        Err(report) => {
+            *stored_char = serde_json::from_value(before).expect("DESERIALIZATION FAILED");
+            *final_char = stored_char.resolve().expect("rollback character resolve failed");
+            return Err(EditError::ResolutionDeadlock { report });
+        }
+    };
+    history.record(before.clone());
+    history.next_revision();
+    if let Err(e) = roster.save(&sheet, &*stored_char, &*history) {
+        *stored_char = serde_json::from_value(before).expect("DESERIALIZATION FAILED");
+        *history = before_history;
+        return Err(e);
+    }
+    *final_char = new_final;
+    Ok(publish(&sheet, &before_final, document(&*final_char, history.revision()), full.unwrap_or(false)))
+}
+
+/// Responds like [edit_character]. Like an [Edit], it can say which revision it is undoing.
+#[post("/character/<id>/undo?<full>&<revision>")]
+fn undo(id: String, full: Option<bool>, revision: Option<u64>, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    travel(&state.inner().roster, &id, full.unwrap_or(false), revision, History::undo, History::redo, EditError::NothingToUndo)
+}
+
+/// Responds like [edit_character]. Like an [Edit], it can say which revision it is redoing from.
+#[post("/character/<id>/redo?<full>&<revision>")]
+fn redo(id: String, full: Option<bool>, revision: Option<u64>, state: State<SharedData>) -> Result<content::Json<String>, EditError> {
+    travel(&state.inner().roster, &id, full.unwrap_or(false), revision, History::redo, History::undo, EditError::NothingToRedo)
+}
+
+/// Turns down changes made against an older revision of the character than the current one.
+fn check_revision(revision: Option<u64>, history: &History) -> Result<(), EditError> {
+    match revision {
+        Some(given) if given != history.revision() => Err(EditError::StaleRevision { given, current: history.revision() }),
+        _ => Ok(())
+    }
+}
+
+/// Moves through the history with `forward`. If the snapshot it lands on can't be loaded,
+/// `back` puts the history back how it was.
+fn travel(
+    roster: &Roster,
+    id: &str,
+    full: bool,
+    revision: Option<u64>,
+    forward: fn(&mut History, Value) -> Option<Value>,
+    back: fn(&mut History, Value) -> Option<Value>,
+    nothing: EditError
+) -> Result<content::Json<String>, EditError> {
+    let sheet = roster.get(id)?;
+    let mut final_char = sheet.final_char.write()
+        .expect("could not get history final write lock");
+    let mut stored_char = sheet.stored_char.write()
+        .expect("could not get history stored write lock");
+    sheet.check_deleted(id)?;
+    let mut history = sheet.history.write()
+        .expect("could not get history write lock");
+
+    check_revision(revision, &history)?;
+
+    let current = serde_json::to_value(&*stored_char).expect("SERIALIZATION FAILED");
+    let before_final = document(&*final_char, history.revision());
+    let before_history = history.clone();
+    let snapshot = forward(&mut *history, current).ok_or(nothing)?;
+    let loaded = StoredCharacter::from_json(snapshot.clone())
+        .and_then(|(mut stored, _)| match stored.resolve() {
+            Ok(resolved) => Ok((stored, resolved)),
+            Err(report) => Err(report.to_string())
+        });
+    let (stored, resolved) = match loaded {
+        Ok(loaded) => loaded,
+        Err(reason) => {
+            back(&mut *history, snapshot);
+            return Err(EditError::UnusableHistory { reason });
+        }
+    };
+    history.next_revision();
+    if let Err(e) = roster.save(&sheet, &stored, &*history) {
+        *history = before_history;
+        return Err(e);
+    }
+    *stored_char = stored;
+    *final_char = resolved;
+    Ok(publish(&sheet, &before_final, document(&*final_char, history.revision()), full))
+}
+
+pub(crate) fn apply_edit(request: EditRequest, stored_char: &mut StoredCharacter) -> Result<(), EditError> {
     use EditRequest::*;
-    match data.into_inner() {
-        Name(s) => (*stored_char).name = s,
-        Description(s) => (*stored_char).description = s,
-        Health(u) => (*stored_char).health = u,
-        TempHealth(u) => (*stored_char).temp_health = u,
-        Race(r) => (*stored_char).race = crate::content::race(r).unwrap(),
+    match request {
+        Name(s) => stored_char.name = s,
+        Description(s) => stored_char.description = s,
+        Health(u) => stored_char.health = u,
+        TempHealth(u) => stored_char.temp_health = u,
+        VariantEncumbrance(b) => stored_char.variant_encumbrance = b,
+        Race(r) => stored_char.race = crate::content::race(r)
+            .ok_or_else(|| EditError::UnknownRace { name: r.to_string() })?,
         Class { index, name} => {
-            if index == (*stored_char).classes.len() {
-                (*stored_char).classes.push(
-                    (crate::content::class(name).unwrap(), 1)
-                );
-            } else {
-                (*stored_char).classes[index] = (crate::content::class(name).unwrap(), 1);
+            let class = crate::content::class(name)
+                .ok_or_else(|| EditError::UnknownClass { name: name.to_string() })?;
+            let len = stored_char.classes.len();
+            match index.cmp(&len) {
+                Ordering::Less => stored_char.classes[index] = (class, 1),
+                Ordering::Equal => stored_char.classes.push((class, 1)),
+                Ordering::Greater => return Err(EditError::ClassIndexOutOfBounds { index, len })
             }
         }
         Level { index, level } => {
-            if level == 0 {
-                (*stored_char).classes.remove(index);
+            let len = stored_char.classes.len();
+            if index >= len {
+                return Err(EditError::ClassIndexOutOfBounds { index, len });
             } else if level > 20 {
-                panic!("levels can't go above 20");
+                return Err(EditError::LevelOutOfRange { level });
+            } else if level == 0 {
+                stored_char.classes.remove(index);
             } else {
-                (*stored_char).classes[index].1 = level;
+                stored_char.classes[index].1 = level;
             }
         }
-        Background(b) => (*stored_char).background = crate::content::background(b).unwrap(),
+        Background(b) => stored_char.background = crate::content::background(b)
+            .ok_or_else(|| EditError::UnknownBackground { name: b.to_string() })?,
         Choice {
-            container,
-            element_index,
+            element,
             choice_index,
             choice
-        } => {
-            match match container {
-                Container::Race => &mut (*final_char).race_traits,
-                Container::Class(index) => &mut (*final_char).class_features[index],
-                Container::Background => &mut (*final_char).background_features,
-                Container::Feat => &mut (*final_char).feats,
-                _ => panic!("no choice container found: {:?}", container)
-            }.get_mut(element_index)
-                .expect(&format!("element index out of bounds: {}", element_index)) {
-                Element::Choice {
-                    data, ..
-                } => unsafe { (**data).choose(choice, choice_index) }
-                _ => panic!("element must be a choice")
-            }
-        }
+        } => edit_element(stored_char, element, ElementEdit::Choose {
+            choice: choice.to_string(),
+            index: choice_index
+        })?,
         Toggle {
-            container,
-            element_index,
+            element,
             toggle_index
-        } => {
-            match match container {
-                Container::Moves => &mut (*final_char).moves,
-                _ => panic!("no toggle container found: {:?}", container)
-            }.get_mut(element_index)
-                .expect(&format!("element index out of bounds: {}", element_index)) {
-                Move::Other {
-                    element: Element::Toggle {
-                        data, ..
-                    }, ..
-                } => unsafe { (**data).toggle(toggle_index) }
-                _ => panic!("element must be a toggle")
-            }
-        }
+        } => edit_element(stored_char, element, ElementEdit::Toggle { index: toggle_index })?,
+        Resource {
+            element,
+            change
+        } => edit_element(stored_char, element, ElementEdit::Resource { change })?,
         Event(e) => {
-            (*stored_char).event(e);
+            stored_char.event(e);
         }
         AbilityScore(a, n) => {
-            match a {
-                Ability::Strength => (*stored_char).base_abilities.strength = n,
-                Ability::Dexterity => (*stored_char).base_abilities.dexterity = n,
-                Ability::Constitution => (*stored_char).base_abilities.constitution = n,
-                Ability::Intelligence => (*stored_char).base_abilities.intelligence = n,
-                Ability::Wisdom => (*stored_char).base_abilities.wisdom = n,
-                Ability::Charisma => (*stored_char).base_abilities.charisma = n,
-                Ability::Unknown => panic!("cannot edit unknown base score")
+            *stored_char.base_abilities.get_mut(a).ok_or(EditError::UnknownAbility)? = n;
+        }
+        AddItem(name) => {
+            let item = crate::content::item(name)
+                .ok_or_else(|| EditError::UnknownItem { name: name.to_string() })?;
+            let equipped = item.equipable().default_equipped();
+            edit_inventory(stored_char, |inventory| {
+                inventory.push((item, equipped, false));
+                Ok(())
+            })?;
+        }
+        RemoveItem { index } => {
+            inventory_item(&mut stored_char.inventory, index)?;
+            stored_char.inventory.remove(index);
+        }
+        Equip { index, equipped } => edit_inventory(stored_char, |inventory| {
+            let (item, current, _) = inventory_item(inventory, index)?;
+            let equipable = item.equipable();
+            if !equipable.allows(equipped) {
+                return Err(EditError::CannotEquip { name: item.name(), equipable, equipped });
+            }
+            *current = equipped;
+            Ok(())
+        })?,
+        Attune { index, attuned } => edit_inventory(stored_char, |inventory| {
+            let (item, _, current) = inventory_item(inventory, index)?;
+            if attuned && !item.attunable() {
+                return Err(EditError::NotAttunable { name: item.name() });
             }
+            *current = attuned;
+            Ok(())
+        })?,
+        Receive { amount, money_type, note } => {
+            let transaction = money::receive(&mut stored_char.money, amount, money_type, note)?;
+            stored_char.ledger.push(transaction);
+        }
+        Spend { amount, money_type, note } => {
+            let transaction = money::spend(&mut stored_char.money, amount, money_type, note)?;
+            stored_char.ledger.push(transaction);
         }
+        Buy { name, note } => {
+            let item = crate::content::item(name)
+                .ok_or_else(|| EditError::UnknownItem { name: name.to_string() })?;
+            let cost = item.cost().ok_or(EditError::NotForSale { name: item.name() })?;
+            let note = if note.is_empty() { format!("Bought {}", item.name()) } else { note };
+            let transaction = money::spend(&mut stored_char.money, cost, MoneyType::Gold, note)?;
+            stored_char.ledger.push(transaction);
+            let equipped = item.equipable().default_equipped();
+            stored_char.inventory.push((item, equipped, false));
+        }
+    }
+    Ok(())
+}
+
+fn inventory_item(inventory: &mut Vec<(Box<dyn Item>, Equipped, bool)>, index: usize) -> Result<&mut (Box<dyn Item>, Equipped, bool), EditError> {
+    let len = inventory.len();
+    inventory.get_mut(index).ok_or(EditError::ItemIndexOutOfBounds { index, len })
+}
+
+/// Makes `edit` to the inventory, unless it breaks a rule that wasn't already broken.
+///
+/// Problems that were there before are let through, so a character file with a broken inventory
+/// can still be fixed one edit at a time.
+fn edit_inventory(
+    stored_char: &mut StoredCharacter,
+    edit: impl FnOnce(&mut Vec<(Box<dyn Item>, Equipped, bool)>) -> Result<(), EditError>
+) -> Result<(), EditError> {
+    let before = common_item_rules::check(&stored_char.inventory);
+    let undo: Vec<(Equipped, bool)> = stored_char.inventory.iter().map(|(_, equipped, attuned)| (*equipped, *attuned)).collect();
+    edit(&mut stored_char.inventory)?;
+    let problems: Vec<InventoryProblem> = common_item_rules::check(&stored_char.inventory).into_iter()
+        .filter(|problem| !before.contains(problem))
+        .collect();
+    if problems.is_empty() {
+        return Ok(());
     }
-    *final_char = stored_char.resolve().expect("edit character resolve failed");
-    std::mem::drop(final_char);
-    stored_char.write(&*state.path);
-    get_character(state)
+    stored_char.inventory.truncate(undo.len());
+    for ((_, equipped, attuned), (old_equipped, old_attuned)) in stored_char.inventory.iter_mut().zip(undo) {
+        *equipped = old_equipped;
+        *attuned = old_attuned;
+    }
+    Err(EditError::InvalidInventory { problems })
+}
+
+/// Makes `edit` to the choice, toggle or resource with the given `id`, wherever it is on the character.
+fn edit_element(stored_char: &mut StoredCharacter, id: String, edit: ElementEdit) -> Result<(), EditError> {
+    crate::ui::edit_element(&id, edit, || stored_char.run_content()).map_err(|e| match e {
+        ElementEditError::NotFound => EditError::UnknownElement { id },
+        ElementEditError::NotAChoice => EditError::NotAChoice { id },
+        ElementEditError::NotAToggle => EditError::NotAToggle { id },
+        ElementEditError::NotAResource => EditError::NotAResource { id },
+        ElementEditError::OutOfRange { max } => EditError::ResourceOutOfRange { id, max },
+        ElementEditError::Refused(e) => e.into()
+    })
 }
 
 #[get("/")]
@@ -228,3 +691,315 @@ fn serve_description(data: Json<Registration>) -> content::Json<String> {
             .expect("could not convert description to json")
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::misc::Hand;
+    use common_item_rules::MAX_ATTUNED;
+
+    fn edit(stored_char: &mut StoredCharacter, json: &str) -> Result<(), EditError> {
+        apply_edit(serde_json::from_str(json).unwrap(), stored_char)
+    }
+
+    /// A quiet server for the character at `path`, that makes new ones in `dir`.
+    fn serve(path: &str, dir: &std::path::Path) -> rocket::local::Client {
+        let settings = Settings {
+            log_level: settings::LogLevel::Off,
+            open_browser: false,
+            ..Settings::default()
+        };
+        rocket::local::Client::new(ignite(&[path.to_string()], dir.to_path_buf(), 0, &settings).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn failed_saves_leave_the_character_alone() {
+        let dir = std::env::temp_dir().join(format!("dndcent-save-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jeebus.json").to_string_lossy().to_string();
+        StoredCharacter::default().write(&path).unwrap();
+        let client = serve(&path, &dir);
+        let rename = || client.post("/character/jeebus/edit")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "Jeebus"}"#)
+            .dispatch();
+
+        // Nowhere to write the file.
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rename().status(), Status::InternalServerError);
+        let mut response = client.post("/character/jeebus").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(!response.body_string().unwrap().contains("Jeebus"));
+        assert_eq!(client.post("/character/jeebus/undo").dispatch().status(), Status::UnprocessableEntity);
+
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(rename().status(), Status::Ok);
+        assert_eq!(StoredCharacter::read(&*dir.join("jeebus.json").to_string_lossy()).name, "Jeebus");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unusable_history_is_put_back() {
+        let dir = std::env::temp_dir().join(format!("dndcent-history-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jeebus.json").to_string_lossy().to_string();
+        StoredCharacter::default().write(&path).unwrap();
+        let mut history = History::default();
+        history.record(serde_json::json!({"name": 5}));
+        history.write(&path).unwrap();
+        let client = serve(&path, &dir);
+
+        for _ in 0..2 {
+            let mut response = client.post("/character/jeebus/undo").dispatch();
+            assert_eq!(response.status(), Status::InternalServerError);
+            assert!(response.body_string().unwrap().contains("unusable_history"));
+        }
+        assert_eq!(client.post("/character/jeebus/redo").dispatch().status(), Status::UnprocessableEntity);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn breakdown_is_served_separately() {
+        let dir = std::env::temp_dir().join(format!("dndcent-breakdown-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jeebus.json").to_string_lossy().to_string();
+        StoredCharacter::default().write(&path).unwrap();
+        let client = serve(&path, &dir);
+
+        let character: Value = serde_json::from_str(&client.post("/character/jeebus").dispatch().body_string().unwrap()).unwrap();
+        assert!(character.get("breakdown").is_none());
+        let breakdown: Value = serde_json::from_str(&client.post("/character/jeebus/breakdown").dispatch().body_string().unwrap()).unwrap();
+        assert!(breakdown["proficiency_bonus"].is_array());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn event_streams_are_limited() {
+        let dir = std::env::temp_dir().join(format!("dndcent-events-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jeebus.json").to_string_lossy().to_string();
+        StoredCharacter::default().write(&path).unwrap();
+        let settings = Settings {
+            log_level: settings::LogLevel::Off,
+            open_browser: false,
+            max_subscribers: 1,
+            ..Settings::default()
+        };
+        let client = rocket::local::Client::new(ignite(&[path], dir.clone(), 0, &settings).unwrap()).unwrap();
+
+        let open = client.get("/character/jeebus/events").dispatch();
+        assert_eq!(open.status(), Status::Ok);
+        let mut refused = client.get("/character/jeebus/events").dispatch();
+        assert_eq!(refused.status(), Status::ServiceUnavailable);
+        assert_eq!(refused.body_string().unwrap(), r#"{"error":"too_many_subscribers","max":1}"#);
+        // The rest of the API still works.
+        assert_eq!(client.post("/character/jeebus").dispatch().status(), Status::Ok);
+
+        std::mem::drop(open);
+        assert_eq!(client.get("/character/jeebus/events").dispatch().status(), Status::Ok);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn inventory_edits_follow_the_items() {
+        let mut stored = StoredCharacter::default();
+        edit(&mut stored, r#"{"add_item": "Longsword"}"#).unwrap();
+        edit(&mut stored, r#"{"add_item": "Cloak of Elvenkind"}"#).unwrap();
+        assert!(matches!(edit(&mut stored, r#"{"add_item": "Vorpal Spoon"}"#), Err(EditError::UnknownItem { .. })));
+
+        edit(&mut stored, r#"{"equip": {"index": 0, "equipped": {"Held": "Both"}}}"#).unwrap();
+        assert!(matches!(
+            edit(&mut stored, r#"{"equip": {"index": 0, "equipped": "Yes"}}"#),
+            Err(EditError::CannotEquip { name: "Longsword", .. })
+        ));
+        edit(&mut stored, r#"{"equip": {"index": 1, "equipped": "Yes"}}"#).unwrap();
+        edit(&mut stored, r#"{"attune": {"index": 1, "attuned": true}}"#).unwrap();
+        assert!(matches!(
+            edit(&mut stored, r#"{"attune": {"index": 0, "attuned": true}}"#),
+            Err(EditError::NotAttunable { name: "Longsword" })
+        ));
+
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.both_hands, Some("Longsword"));
+        assert_eq!(c.inventory.len(), 2);
+        assert!(c.inventory[1].attuned);
+
+        edit(&mut stored, r#"{"remove_item": {"index": 0}}"#).unwrap();
+        assert!(matches!(
+            edit(&mut stored, r#"{"remove_item": {"index": 1}}"#),
+            Err(EditError::ItemIndexOutOfBounds { index: 1, len: 1 })
+        ));
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.both_hands, None);
+        assert_eq!(c.inventory[0].name, "Cloak of Elvenkind");
+    }
+
+    #[test]
+    fn inventory_rules_are_enforced() {
+        use crate::moves::Move;
+
+        fn longsword_dice(stored: &mut StoredCharacter) -> Vec<u32> {
+            stored.resolve().unwrap().moves.iter().filter_map(|m| match m {
+                Move::Attack { name: "Longsword", damage, .. } => Some(*damage.dice.keys().next().unwrap()),
+                _ => None
+            }).collect()
+        }
+
+        let mut stored = StoredCharacter::default();
+        edit(&mut stored, r#"{"add_item": "Longsword"}"#).unwrap();
+        edit(&mut stored, r#"{"equip": {"index": 0, "equipped": {"Held": "Right"}}}"#).unwrap();
+        assert_eq!(longsword_dice(&mut stored), vec! [10]);
+
+        edit(&mut stored, r#"{"add_item": "Shield"}"#).unwrap();
+        edit(&mut stored, r#"{"equip": {"index": 1, "equipped": {"Held": "Left"}}}"#).unwrap();
+        assert_eq!(longsword_dice(&mut stored), vec! [8]);
+        match edit(&mut stored, r#"{"equip": {"index": 0, "equipped": {"Held": "Both"}}}"#) {
+            Err(EditError::InvalidInventory { problems }) => assert_eq!(problems, vec! [
+                InventoryProblem::HandInUse { hand: Hand::Left, items: vec! ["Longsword", "Shield"] }
+            ]),
+            other => panic!("{:?}", other)
+        }
+        assert_eq!(stored.inventory[0].1, Equipped::Held(Hand::Right));
+
+        edit(&mut stored, r#"{"add_item": "Padded"}"#).unwrap();
+        edit(&mut stored, r#"{"equip": {"index": 2, "equipped": "Yes"}}"#).unwrap();
+        edit(&mut stored, r#"{"add_item": "Padded"}"#).unwrap();
+        assert!(matches!(
+            edit(&mut stored, r#"{"equip": {"index": 3, "equipped": "Yes"}}"#),
+            Err(EditError::InvalidInventory { .. })
+        ));
+
+        for index in 4..4 + MAX_ATTUNED {
+            edit(&mut stored, r#"{"add_item": "Cloak of Elvenkind"}"#).unwrap();
+            edit(&mut stored, &format!(r#"{{"attune": {{"index": {}, "attuned": true}}}}"#, index)).unwrap();
+        }
+        edit(&mut stored, r#"{"add_item": "Cloak of Elvenkind"}"#).unwrap();
+        assert!(matches!(
+            edit(&mut stored, &format!(r#"{{"attune": {{"index": {}, "attuned": true}}}}"#, 4 + MAX_ATTUNED)),
+            Err(EditError::InvalidInventory { .. })
+        ));
+        assert!(stored.resolve().unwrap().warnings.is_empty());
+
+        stored.inventory[0].1 = Equipped::Held(Hand::Both);
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.warnings.len(), 1);
+        edit(&mut stored, r#"{"equip": {"index": 1, "equipped": "No"}}"#).unwrap();
+        assert!(stored.resolve().unwrap().warnings.is_empty());
+        assert_eq!(longsword_dice(&mut stored), vec! [10]);
+    }
+
+    #[test]
+    fn armor_class_comes_from_armor_and_shields() {
+        use crate::misc::Vantage;
+
+        let mut stored = StoredCharacter::default();
+        stored.base_abilities.dexterity = 16;
+        assert_eq!(stored.resolve().unwrap().armor_class, 13);
+
+        edit(&mut stored, r#"{"add_item": "Padded"}"#).unwrap();
+        edit(&mut stored, r#"{"equip": {"index": 0, "equipped": "Yes"}}"#).unwrap();
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.armor_class, 14);
+        assert!(matches!(c.skill_vantages.stealth, Vantage::Disadvantage));
+
+        edit(&mut stored, r#"{"add_item": "Shield"}"#).unwrap();
+        edit(&mut stored, r#"{"equip": {"index": 1, "equipped": {"Held": "Left"}}}"#).unwrap();
+        assert_eq!(stored.resolve().unwrap().armor_class, 16);
+        edit(&mut stored, r#"{"add_item": "Shield"}"#).unwrap();
+        assert!(matches!(
+            edit(&mut stored, r#"{"equip": {"index": 2, "equipped": {"Held": "Right"}}}"#),
+            Err(EditError::InvalidInventory { .. })
+        ));
+
+        edit(&mut stored, r#"{"equip": {"index": 0, "equipped": "No"}}"#).unwrap();
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.armor_class, 15);
+        assert!(matches!(c.skill_vantages.stealth, Vantage::None));
+
+        stored.base_abilities.strength = 11;
+        edit(&mut stored, r#"{"race": "Human"}"#).unwrap();
+        edit(&mut stored, r#"{"add_item": "Chain Mail"}"#).unwrap();
+        edit(&mut stored, r#"{"equip": {"index": 3, "equipped": "Yes"}}"#).unwrap();
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.armor_class, 18);
+        assert_eq!(c.speeds.walk, 20);
+        stored.base_abilities.strength = 12;
+        assert_eq!(stored.resolve().unwrap().speeds.walk, 30);
+    }
+
+    #[test]
+    fn heavy_loads_slow_the_character_down() {
+        use crate::misc::{Encumbrance, Vantage};
+
+        let mut stored = StoredCharacter::default();
+        stored.base_abilities.strength = 7;
+        edit(&mut stored, r#"{"race": "Halfling"}"#).unwrap();
+        let c = stored.resolve().unwrap();
+        assert_eq!((c.carrying_capacity, c.push_drag_lift), (105, 210));
+
+        for _ in 0..2 {
+            edit(&mut stored, r#"{"add_item": "Chain Mail"}"#).unwrap();
+        }
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.carried_weight, 110);
+        assert_eq!(c.encumbrance, Encumbrance::OverCapacity);
+        assert_eq!(c.speeds.walk, 5);
+
+        edit(&mut stored, r#"{"remove_item": {"index": 1}}"#).unwrap();
+        edit(&mut stored, r#"{"variant_encumbrance": true}"#).unwrap();
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.encumbrance_thresholds, Some((35, 70)));
+        assert_eq!(c.encumbrance, Encumbrance::Encumbered);
+        assert_eq!(c.speeds.walk, 15);
+        assert!(matches!(c.save_vantages.strength, Vantage::None));
+
+        edit(&mut stored, r#"{"add_item": "Padded"}"#).unwrap();
+        edit(&mut stored, r#"{"add_item": "Shield"}"#).unwrap();
+        edit(&mut stored, r#"{"add_item": "Longsword"}"#).unwrap();
+        let c = stored.resolve().unwrap();
+        assert_eq!(c.carried_weight, 72);
+        assert_eq!(c.encumbrance, Encumbrance::HeavilyEncumbered);
+        assert_eq!(c.speeds.walk, 5);
+        assert!(matches!(c.save_vantages.strength, Vantage::Disadvantage));
+        assert!(matches!(c.skill_vantages.athletics, Vantage::Disadvantage));
+        assert!(matches!(c.skill_vantages.arcana, Vantage::None));
+    }
+
+    #[test]
+    fn items_are_bought_with_coins() {
+        let mut stored = StoredCharacter::default();
+        edit(&mut stored, r#"{"receive": {"amount": 2, "money_type": "Platinum", "note": "Reward"}}"#).unwrap();
+        edit(&mut stored, r#"{"buy": {"name": "Longsword"}}"#).unwrap();
+        edit(&mut stored, r#"{"spend": {"amount": 5, "money_type": "Silver"}}"#).unwrap();
+        assert!(matches!(
+            edit(&mut stored, r#"{"buy": {"name": "Chain Mail"}}"#),
+            Err(EditError::InsufficientFunds { needed: 7500, available: 450 })
+        ));
+        assert!(matches!(
+            edit(&mut stored, r#"{"spend": {"amount": 1, "money_type": "Unknown"}}"#),
+            Err(EditError::UnknownMoneyType)
+        ));
+
+        let c = stored.resolve().unwrap();
+        assert_eq!((c.money.platinum, c.money.gold, c.money.electrum, c.money.silver), (0, 4, 1, 0));
+        assert_eq!(c.inventory.len(), 1);
+        assert_eq!(c.inventory[0].name, "Longsword");
+        let notes: Vec<&str> = c.ledger.iter().map(|t| t.note.as_str()).collect();
+        assert_eq!(notes, vec! ["Reward", "Bought Longsword", ""]);
+        assert_eq!(c.ledger[1].amount, -15);
+    }
+
+    #[test]
+    fn deposits_that_overflow_are_refused() {
+        let mut stored = StoredCharacter::default();
+        let deposit = format!(r#"{{"receive": {{"amount": {}, "money_type": "Gold"}}}}"#, u32::MAX);
+        edit(&mut stored, &deposit).unwrap();
+        match edit(&mut stored, &deposit) {
+            Err(e @ EditError::TooMuchMoney { .. }) => assert_eq!(e.status(), Status::UnprocessableEntity),
+            other => panic!("{:?}", other)
+        }
+        assert_eq!(stored.money.gold, u32::MAX);
+        assert_eq!(stored.ledger.len(), 1);
+        assert_eq!(stored.resolve().unwrap().money.gold, u32::MAX);
+    }
+}
@@ -0,0 +1,106 @@
+//! Where and how the server runs.
+//!
+//! Settings come from the defaults, then an optional TOML config file given with `--config`,
+//! then the command line flags, each overriding the one before. A config file looks like:
+//!
+//! ```toml
+//! address = "0.0.0.0"
+//! port = 8080
+//! log-level = "normal"
+//! open-browser = false
+//! max-subscribers = 48
+//! ```
+
+use std::str::FromStr;
+use serde::Deserialize;
+use rocket::config::LoggingLevel;
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    pub address: String,
+    pub port: u16,
+    pub log_level: LogLevel,
+    pub open_browser: bool,
+    /// How many pages can follow characters' changes at once. Each one holds a server worker.
+    pub max_subscribers: usize
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            address: "localhost".to_string(),
+            port: 8000,
+            log_level: if cfg!(debug_assertions) { LogLevel::Normal } else { LogLevel::Off },
+            open_browser: cfg!(not(debug_assertions)),
+            max_subscribers: 48
+        }
+    }
+}
+
+impl Settings {
+    pub fn read(path: &str) -> Result<Settings, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read config file {}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("Could not read config file {}: {}", path, e))
+    }
+
+    /// Where to point a browser. A server listening on every address is still reachable on localhost.
+    pub fn url(&self) -> String {
+        let host = match &*self.address {
+            "0.0.0.0" | "::" | "[::]" => "localhost",
+            address => address
+        };
+        format!("http://{}:{}", host, self.port)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Critical,
+    Normal,
+    Debug
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(LogLevel::Off),
+            "critical" => Ok(LogLevel::Critical),
+            "normal" => Ok(LogLevel::Normal),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(())
+        }
+    }
+}
+
+impl From<LogLevel> for LoggingLevel {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LoggingLevel::Off,
+            LogLevel::Critical => LoggingLevel::Critical,
+            LogLevel::Normal => LoggingLevel::Normal,
+            LogLevel::Debug => LoggingLevel::Debug
+        }
+    }
+}
+
+/// Opens `url` in the default browser, without waiting for it. Failing is not a big deal, the
+/// url is printed anyway.
+pub fn open_browser(url: &str) {
+    use std::process::Command;
+
+    let result = if cfg!(target_os = "windows") {
+        Command::new("cmd").args(&["/C", "start", url]).spawn()
+    } else if cfg!(target_os = "macos") {
+        Command::new("open").arg(url).spawn()
+    } else {
+        Command::new("xdg-open").arg(url).spawn()
+    };
+    if let Err(e) = result {
+        eprintln!("WARNING: could not open a browser: {}", e);
+    }
+}
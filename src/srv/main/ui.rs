@@ -1,5 +1,6 @@
 use serde::Serializer;
 use std::fmt::Debug;
+use std::cell::{Cell, RefCell};
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Deserialize};
 
@@ -7,25 +8,34 @@ use serde::{Serialize, Deserialize};
 pub enum Element<'a> {
     Str(&'a str),
     String(String),
+    /// Content writes `data: &mut self.field`, and the stage macros turn it into a snapshot of
+    /// the field with [choice_data] and fill in `id`, see [element_id](crate::character::element_id).
     Choice {
         text: &'a str,
-        data: *mut dyn Chooseable,
-        unique: bool
+        data: ChoiceSerial,
+        unique: bool,
+        id: String
     },
+    /// A limited-use feature. Content writes `data: &mut self.field`, where the field counts the
+    /// uses spent, and the stage macros turn it into the uses left with [resource_data].
     Resource {
         text: &'a str,
-        data: *mut u32,
-        max: u32
+        data: u32,
+        max: u32,
+        recharge: Recharge,
+        id: String
     },
     Trigger {
         text: &'a str,
         event: Event<'a>,
         button: &'a str
     },
+    /// Content also writes `data: &mut self.field` here, which the stage macros take out and
+    /// hand to [toggle_data].
     Toggle {
         text: &'static str,
-        data: *mut dyn Toggleable,
-        button: Vec<&'a str>
+        button: Vec<&'a str>,
+        id: String
     }
 }
 
@@ -44,19 +54,22 @@ impl serde::Serialize for Element<'_> {
                 state.serialize_field("type", "text")?;
                 state.serialize_field("text", text)?;
             }
-            Element::Choice { text, data, unique } => unsafe {
-                state = serializer.serialize_struct("Element", 4)?;
+            Element::Choice { text, data, unique, id } => {
+                state = serializer.serialize_struct("Element", 5)?;
                 state.serialize_field("type", "choice")?;
+                state.serialize_field("id", id)?;
                 state.serialize_field("text", text)?;
-                state.serialize_field("data", &(**data).to_serial())?;
+                state.serialize_field("data", data)?;
                 state.serialize_field("unique", unique)?;
             }
-            Element::Resource { text, data, max } => unsafe {
-                state = serializer.serialize_struct("Element", 4)?;
+            Element::Resource { text, data, max, recharge, id } => {
+                state = serializer.serialize_struct("Element", 6)?;
                 state.serialize_field("type", "resource")?;
+                state.serialize_field("id", id)?;
                 state.serialize_field("text", text)?;
-                state.serialize_field("data", &**data)?;
-                state.serialize_field("unique", max)?;
+                state.serialize_field("data", data)?;
+                state.serialize_field("max", max)?;
+                state.serialize_field("recharge", recharge)?;
             }
             Element::Trigger { text, event, button } => {
                 state = serializer.serialize_struct("Element", 4)?;
@@ -65,9 +78,10 @@ impl serde::Serialize for Element<'_> {
                 state.serialize_field("event", event)?;
                 state.serialize_field("button", button)?;
             }
-            Element::Toggle { text, button, ..} => {
-                state = serializer.serialize_struct("Element", 3)?;
+            Element::Toggle { text, button, id } => {
+                state = serializer.serialize_struct("Element", 4)?;
                 state.serialize_field("type", "toggle")?;
+                state.serialize_field("id", id)?;
                 state.serialize_field("text", text)?;
                 state.serialize_field("button", button)?;
             }
@@ -76,14 +90,44 @@ impl serde::Serialize for Element<'_> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum Event<'a> {
     LongRest,
     ShortRest,
     Other(&'a str)
 }
 
-#[derive(Serialize, Clone)]
+/// Which rests bring back the uses of a [Resource](Element::Resource).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recharge {
+    /// A short or a long rest.
+    ShortRest,
+    LongRest,
+    Never
+}
+
+impl Recharge {
+    fn on(self, event: &Event) -> bool {
+        matches!(
+            (self, event),
+            (Recharge::ShortRest, Event::ShortRest)
+            | (Recharge::ShortRest, Event::LongRest)
+            | (Recharge::LongRest, Event::LongRest)
+        )
+    }
+}
+
+/// A change to the uses left of a [Resource](Element::Resource).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceChange {
+    Increment,
+    Decrement,
+    Set(u32)
+}
+
+#[derive(Debug, Serialize, Clone)]
 pub struct ChoiceSerial {
     pub current_choices: Vec<&'static str>,
     pub all_choices: Vec<&'static str>
@@ -98,41 +142,194 @@ impl ChoiceSerial {
     }
 }
 
+/// Why a [Chooseable] or [Toggleable] refused to change.
+///
+/// The value is left untouched whenever one of these is returned.
+#[derive(Debug, Serialize, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChoiceError {
+    NotFound(String),
+    IndexOutOfBounds {
+        index: usize,
+        len: usize
+    }
+}
+
+/// A change to the content behind an element, made with [edit_element].
+#[derive(Debug, Clone)]
+pub enum ElementEdit {
+    Choose {
+        choice: String,
+        index: usize
+    },
+    Toggle {
+        index: usize
+    },
+    Resource {
+        change: ResourceChange
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ElementEditError {
+    NotFound,
+    NotAChoice,
+    NotAToggle,
+    NotAResource,
+    Refused(ChoiceError),
+    /// The uses left of a resource would go below zero or above `max`.
+    OutOfRange {
+        max: u32
+    }
+}
+
+struct PendingEdit {
+    id: String,
+    edit: ElementEdit,
+    result: Option<Result<(), ElementEditError>>
+}
+
+thread_local! {
+    static PENDING_EDIT: RefCell<Option<PendingEdit>> = RefCell::new(None);
+    static RESTING: Cell<Option<Event<'static>>> = Cell::new(None);
+}
+
+/// Runs `clear` when dropped, so a resolve that panics doesn't leave an edit or rest behind for
+/// the next character resolved on the same thread.
+struct ClearGuard(fn());
+
+impl Drop for ClearGuard {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+/// Makes `edit` to the element `id` while `resolve` runs.
+///
+/// Elements don't keep a way back to the content that made them. Instead the content is run
+/// again, and when the stage that builds the element runs, [choice_data] or [toggle_data] makes the
+/// edit through the `&mut self` that stage already has. Whatever `resolve` builds is made partly
+/// before and partly after the edit, so it should be thrown away.
+pub fn edit_element<T>(id: &str, edit: ElementEdit, resolve: impl FnOnce() -> T) -> Result<(), ElementEditError> {
+    PENDING_EDIT.with(|pending| *pending.borrow_mut() = Some(PendingEdit {
+        id: id.to_string(),
+        edit,
+        result: None
+    }));
+    let _clear = ClearGuard(|| PENDING_EDIT.with(|pending| *pending.borrow_mut() = None));
+    resolve();
+    PENDING_EDIT.with(|pending| pending.borrow_mut().take())
+        .and_then(|pending| pending.result)
+        .unwrap_or(Err(ElementEditError::NotFound))
+}
+
+/// Takes the pending edit if it is for the element `id`, and hasn't been made yet.
+fn pending_edit(id: &str, make: impl FnOnce(&ElementEdit) -> Result<(), ElementEditError>) {
+    PENDING_EDIT.with(|pending| {
+        if let Some(pending) = &mut *pending.borrow_mut() {
+            if pending.id == id && pending.result.is_none() {
+                pending.result = Some(make(&pending.edit));
+            }
+        }
+    });
+}
+
+/// Brings back the uses of every resource that recharges on `rest` while `resolve` runs, the
+/// same way [edit_element] makes its edit.
+pub fn rest<T>(rest: &Event, resolve: impl FnOnce() -> T) {
+    let rest = match rest {
+        Event::ShortRest => Event::ShortRest,
+        Event::LongRest => Event::LongRest,
+        Event::Other(_) => return
+    };
+    RESTING.with(|resting| resting.set(Some(rest)));
+    let _clear = ClearGuard(|| RESTING.with(|resting| resting.set(None)));
+    resolve();
+}
+
+/// The `data` of an [Element::Choice], after making the pending edit if it is for this element.
+pub fn choice_data(id: &str, data: &mut dyn Chooseable) -> ChoiceSerial {
+    pending_edit(id, |edit| match edit {
+        ElementEdit::Choose { choice, index } => data.choose(choice, *index).map_err(ElementEditError::Refused),
+        ElementEdit::Toggle { .. } => Err(ElementEditError::NotAToggle),
+        ElementEdit::Resource { .. } => Err(ElementEditError::NotAResource)
+    });
+    data.to_serial()
+}
+
+/// Makes the pending edit if it is for this [Element::Toggle].
+pub fn toggle_data(id: &str, data: &mut dyn Toggleable) {
+    pending_edit(id, |edit| match edit {
+        ElementEdit::Toggle { index } => data.toggle(*index).map_err(ElementEditError::Refused),
+        ElementEdit::Choose { .. } => Err(ElementEditError::NotAChoice),
+        ElementEdit::Resource { .. } => Err(ElementEditError::NotAResource)
+    });
+}
+
+/// The uses left of an [Element::Resource], after recharging it if the character is resting and
+/// making the pending edit if it is for this element.
+///
+/// `spent` is stored instead of the uses left, so a new character, or a bigger `max` after
+/// leveling up, starts out with every use.
+pub fn resource_data(id: &str, spent: &mut u32, max: u32, recharge: Recharge) -> u32 {
+    if RESTING.with(|resting| resting.get()).map_or(false, |rest| recharge.on(&rest)) {
+        *spent = 0;
+    }
+    pending_edit(id, |edit| match edit {
+        ElementEdit::Resource { change } => {
+            let left = max.saturating_sub(*spent);
+            let left = match change {
+                ResourceChange::Increment => left.checked_add(1),
+                ResourceChange::Decrement => left.checked_sub(1),
+                ResourceChange::Set(n) => Some(*n)
+            }.filter(|left| *left <= max).ok_or(ElementEditError::OutOfRange { max })?;
+            *spent = max - left;
+            Ok(())
+        }
+        ElementEdit::Choose { .. } => Err(ElementEditError::NotAChoice),
+        ElementEdit::Toggle { .. } => Err(ElementEditError::NotAToggle)
+    });
+    max.saturating_sub(*spent)
+}
+
 pub trait Chooseable: Debug {
-    fn choose(&mut self, choice: &str, index: usize);
+    fn choose(&mut self, choice: &str, index: usize) -> Result<(), ChoiceError>;
     fn to_serial(&self) -> ChoiceSerial;
 }
 
 pub trait Toggleable {
-    fn toggle(&mut self, index: usize);
+    fn toggle(&mut self, index: usize) -> Result<(), ChoiceError>;
 }
 
 impl Toggleable for bool {
-    fn toggle(&mut self, index: usize) {
+    fn toggle(&mut self, index: usize) -> Result<(), ChoiceError> {
         if index == 0 {
             *self = !*self;
+            Ok(())
         } else {
-            panic!("toggle index out of bounds: {}. length is 1", index)
+            Err(ChoiceError::IndexOutOfBounds { index, len: 1 })
         }
     }
 }
 
 impl<const N: usize> Toggleable for [bool; N] {
-    fn toggle(&mut self, index: usize) {
+    fn toggle(&mut self, index: usize) -> Result<(), ChoiceError> {
         if index < N {
-            self[index] = !self[index]
+            self[index] = !self[index];
+            Ok(())
         } else {
-            panic!("toggle index out of bounds: {}. length is {}", index, N)
+            Err(ChoiceError::IndexOutOfBounds { index, len: N })
         }
     }
 }
 
 impl Toggleable for Vec<bool> {
-    fn toggle(&mut self, index: usize) {
+    fn toggle(&mut self, index: usize) -> Result<(), ChoiceError> {
         if index < self.len() {
-            self[index] = !self[index]
+            self[index] = !self[index];
+            Ok(())
         } else {
-            panic!("toggle index out of bounds: {}. length is {}", index, self.len())
+            Err(ChoiceError::IndexOutOfBounds { index, len: self.len() })
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,150 @@
+//! Loading characters that mention content this build doesn't have.
+//!
+//! Content is stored by its typetag name, like `{"Alert": null}`, so a file that uses a homebrew
+//! feat which has since been removed can't be deserialized as is. Instead of failing, the
+//! deserializer for each kind of content (see [dynamic_choose](proc_macros::dynamic_choose))
+//! wraps a name it doesn't know in that kind's `system::defaults` placeholder, like
+//! `{"UnknownFeat": {"HomebrewFeat": {...}}}`. The placeholder holds on to the original JSON,
+//! and [restore] unwraps it again when the character is saved, so nothing is lost.
+
+use serde::de::{DeserializeOwned, Error};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use crate::content::PLACEHOLDERS;
+
+/// One unknown typetag name that was swapped for a placeholder.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Replaced {
+    pub name: String,
+    pub placeholder: &'static str,
+    pub count: usize
+}
+
+impl Display for Replaced {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown content `{}` ({} {}) was loaded as {}; it will be saved unchanged",
+            self.name,
+            self.count,
+            if self.count == 1 { "place" } else { "places" },
+            self.placeholder
+        )
+    }
+}
+
+thread_local! {
+    static REPLACED: RefCell<Vec<Replaced>> = RefCell::new(vec! []);
+}
+
+/// Deserializes `json`, and says which unknown content was loaded as placeholders.
+pub fn deserialize<T: DeserializeOwned>(json: Value) -> Result<(T, Vec<Replaced>), serde_json::Error> {
+    REPLACED.with(|replaced| replaced.borrow_mut().clear());
+    let result = serde_json::from_value(json);
+    let replaced = REPLACED.with(|replaced| replaced.replace(vec! []));
+    result.map(|t| (t, replaced))
+}
+
+/// Splits `{name: value}` into its name and value.
+pub(crate) fn split_tag<E: Error>(map: Map<String, Value>) -> Result<(String, Value), E> {
+    if map.len() != 1 {
+        return Err(E::invalid_length(map.len(), &"an object with exactly one content name"));
+    }
+    Ok(map.into_iter().next().unwrap())
+}
+
+/// The other way around from [split_tag].
+pub(crate) fn tagged(name: String, value: Value) -> Value {
+    let mut map = Map::new();
+    map.insert(name, value);
+    Value::Object(map)
+}
+
+/// Notes that content called `name` was loaded as `placeholder`.
+pub(crate) fn replaced(name: &str, placeholder: &'static str) {
+    REPLACED.with(|replaced| {
+        let mut replaced = replaced.borrow_mut();
+        match replaced.iter_mut().find(|r| r.name == name && r.placeholder == placeholder) {
+            Some(r) => r.count += 1,
+            None => replaced.push(Replaced { name: name.to_string(), placeholder, count: 1 })
+        }
+    });
+}
+
+/// Unwraps every placeholder, putting the original JSON back where it was.
+pub fn restore(json: &mut Value) {
+    match json {
+        Value::Object(object) if object.len() == 1 => {
+            let (key, inner) = object.iter().next().unwrap();
+            let is_original = matches!(inner, Value::Object(o) if o.len() == 1);
+            if PLACEHOLDERS.contains(&key.as_str()) && is_original {
+                let inner = object.values_mut().next().unwrap().take();
+                *json = inner;
+            } else {
+                restore(object.values_mut().next().unwrap());
+            }
+        }
+        Value::Object(object) => object.values_mut().for_each(restore),
+        Value::Array(array) => array.iter_mut().for_each(restore),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::character::StoredCharacter;
+
+    fn with_unknown_content() -> Value {
+        let json = std::fs::read_to_string("test_character.json").unwrap();
+        let mut json: Value = serde_json::from_str(&json).unwrap();
+        json["race"]["VariantHuman"]["feat"] = json!({ "HomebrewFeat": { "bonus": 3 } });
+        json["inventory"][3][0] = json!({ "BagOfHolding": null });
+        json["classes"][0][0]["Rogue"]["subclass"] = json!({ "Swashbuckler": null });
+        crate::migration::migrate(json).unwrap()
+    }
+
+    #[test]
+    fn unknown_content_loads_as_placeholders() {
+        let (_, replaced): (StoredCharacter, _) = deserialize(with_unknown_content()).unwrap();
+        let mut replaced: Vec<(String, &str)> = replaced.into_iter().map(|r| (r.name, r.placeholder)).collect();
+        replaced.sort();
+        assert_eq!(replaced, vec! [
+            ("BagOfHolding".to_string(), "UnknownItem"),
+            ("HomebrewFeat".to_string(), "UnknownFeat"),
+            ("Swashbuckler".to_string(), "UnknownRoguishArchetype")
+        ]);
+    }
+
+    #[test]
+    fn placeholders_round_trip() {
+        let original = with_unknown_content();
+        let (stored, _): (StoredCharacter, _) = deserialize(original.clone()).unwrap();
+        let mut saved = serde_json::to_value(&stored).unwrap();
+        restore(&mut saved);
+        assert_eq!(saved, original);
+    }
+
+    #[test]
+    fn only_content_is_replaced() {
+        // An item that happens to share its name with a way of equipping things.
+        let mut original = with_unknown_content();
+        original["inventory"][1][0] = json!({ "Held": null });
+        let (stored, replaced): (StoredCharacter, _) = deserialize(original.clone()).unwrap();
+        assert!(replaced.contains(&Replaced { name: "Held".to_string(), placeholder: "UnknownItem", count: 1 }));
+        assert_eq!(stored.inventory[1].1, crate::misc::Equipped::Held(crate::misc::Hand::Left));
+
+        let mut saved = serde_json::to_value(&stored).unwrap();
+        restore(&mut saved);
+        assert_eq!(saved, original);
+    }
+
+    #[test]
+    fn other_errors_are_not_replaced() {
+        let mut json = with_unknown_content();
+        json["health"] = json!("lots");
+        assert!(deserialize::<StoredCharacter>(json).is_err());
+    }
+}
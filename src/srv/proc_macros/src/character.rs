@@ -20,6 +20,7 @@ pub(crate) fn finalize(ast: syn::DeriveInput) -> TokenStream {
             }
         ) => {
             let mut count_unresolved_acc = quote! {0};
+            let mut visit_acc = quote! {};
             let mut final_character_acc = quote! {};
             let mut finalize_acc = quote! {};
 
@@ -44,6 +45,10 @@ pub(crate) fn finalize(ast: syn::DeriveInput) -> TokenStream {
                                 #count_unresolved_acc
                                 + self.#id.count_unresolved()
                             };
+                            visit_acc = quote! {
+                                #visit_acc
+                                self.#id.visit(stringify!(#id), f);
+                            };
                             final_character_acc = quote! {
                                 #final_character_acc
                                 pub #id: #target,
@@ -59,6 +64,10 @@ pub(crate) fn finalize(ast: syn::DeriveInput) -> TokenStream {
                                 #count_unresolved_acc
                                 + self.#id.count_unresolved()
                             };
+                            visit_acc = quote! {
+                                #visit_acc
+                                self.#id.visit(stringify!(#id), f);
+                            };
                             final_character_acc = quote! {
                                 #final_character_acc
                                 pub #id: #map_ident<#target>,
@@ -95,9 +104,14 @@ pub(crate) fn finalize(ast: syn::DeriveInput) -> TokenStream {
                     fn count_unresolved(&self) -> u32 {
                         #count_unresolved_acc
                     }
+                    pub fn visit_stages(&self, f: &mut dyn FnMut(&str, StagedView)) {
+                        #visit_acc
+                    }
                     pub fn finalize(self) -> FinalCharacter {
+                        let breakdown = self.breakdown();
                         FinalCharacter {
                             #finalize_acc
+                            breakdown
                         }
                     }
                 }
@@ -105,6 +119,10 @@ pub(crate) fn finalize(ast: syn::DeriveInput) -> TokenStream {
                 #[derive(Serialize, Debug, Default)]
                 pub struct FinalCharacter {
                     #final_character_acc
+                    /// Served on its own by `/character/<id>/breakdown`; it's bigger than the
+                    /// rest of the character put together.
+                    #[serde(skip)]
+                    pub breakdown: crate::character::Breakdown
                 }
             }).into()
         }
@@ -40,6 +40,7 @@ pub fn choose(ast: syn::ItemEnum) -> TokenStream {
     let mut map_get_rules = "".to_string();
     let mut map_get_mut_rules = "".to_string();
     let mut map_count = "".to_string();
+    let mut map_visit = "".to_string();
     let mut serialize_rules = "".to_string();
     let mut deserialize_rules = "".to_string();
     for (i, (var, pretty)) in vars.iter().enumerate() {
@@ -53,6 +54,7 @@ pub fn choose(ast: syn::ItemEnum) -> TokenStream {
             map_get_rules.extend(format!("{}::{} => Some(&self.{}),\n", name, var, snake_var).chars());
             map_get_mut_rules.extend(format!("{}::{} => Some(&mut self.{}),\n", name, var, snake_var).chars());
             map_count.extend(format!("self.{}.count_unresolved() +\n", snake_var).chars());
+            map_visit.extend(format!("self.{}.visit(&format!(\"{{}}.{}\", field), f);\n", snake_var, snake_var).chars());
         }
         reverse_match_rules.extend(format!(r#"{}::{} => "{}",{}"#, name, var, pretty, "\n").chars());
         serialize_rules.extend(format!("{}::{} => serializer.serialize_unit_variant(\"{}\", {}, \"{}\"),\n", name, var, name, i, pretty).chars());
@@ -67,6 +69,7 @@ pub fn choose(ast: syn::ItemEnum) -> TokenStream {
     let map_get_rules_tokens: TokenStream2 = map_get_rules.parse().expect("map get rules parse failed");
     let map_get_mut_rules_tokens: TokenStream2 = map_get_mut_rules.parse().expect("map get mut rules parse failed");
     let map_count_tokens: TokenStream2 = map_count.parse().expect("map count parse failed");
+    let map_visit_tokens: TokenStream2 = map_visit.parse().expect("map visit parse failed");
     let serialize_rules_tokens: TokenStream2 = serialize_rules.parse().expect("serialize rules parse failed");
     let deserialize_rules_tokens: TokenStream2 = deserialize_rules.parse().expect("deserialize rules parse failed");
 
@@ -90,14 +93,15 @@ pub fn choose(ast: syn::ItemEnum) -> TokenStream {
         }
 
         impl Chooseable for #enum_ident {
-            fn choose(&mut self, choice: &str, index: usize) {
+            fn choose(&mut self, choice: &str, index: usize) -> Result<(), crate::ui::ChoiceError> {
                 if index == 0 {
                     *self = match choice {
                         #match_rules_tokens
-                        _ => panic!("choice not found: {}", choice)
-                    }
+                        _ => return Err(crate::ui::ChoiceError::NotFound(choice.to_string()))
+                    };
+                    Ok(())
                 } else {
-                    panic!("choice index should be zero for single choice, was {}", index)
+                    Err(crate::ui::ChoiceError::IndexOutOfBounds { index, len: 1 })
                 }
             }
             fn to_serial(&self) -> crate::ui::ChoiceSerial {
@@ -109,12 +113,15 @@ pub fn choose(ast: syn::ItemEnum) -> TokenStream {
         }
 
         impl<const N: usize> Chooseable for [#enum_ident; N] {
-            fn choose(&mut self, choice: &str, index: usize) {
+            fn choose(&mut self, choice: &str, index: usize) -> Result<(), crate::ui::ChoiceError> {
                 if index < N {
                     self[index] = match choice {
                         #match_rules_tokens
-                        _ => panic!("choice not found: {}", choice)
-                    }
+                        _ => return Err(crate::ui::ChoiceError::NotFound(choice.to_string()))
+                    };
+                    Ok(())
+                } else {
+                    Err(crate::ui::ChoiceError::IndexOutOfBounds { index, len: N })
                 }
             }
             fn to_serial(&self) -> crate::ui::ChoiceSerial {
@@ -125,12 +132,15 @@ pub fn choose(ast: syn::ItemEnum) -> TokenStream {
         }
 
         impl Chooseable for Vec<#enum_ident> {
-            fn choose(&mut self, choice: &str, index: usize) {
+            fn choose(&mut self, choice: &str, index: usize) -> Result<(), crate::ui::ChoiceError> {
                 if index < self.len() {
                     self[index] = match choice {
                         #match_rules_tokens
-                        _ => panic!("choice not found: {}", choice)
-                    }
+                        _ => return Err(crate::ui::ChoiceError::NotFound(choice.to_string()))
+                    };
+                    Ok(())
+                } else {
+                    Err(crate::ui::ChoiceError::IndexOutOfBounds { index, len: self.len() })
                 }
             }
             fn to_serial(&self) -> crate::ui::ChoiceSerial {
@@ -199,6 +209,10 @@ pub fn choose(ast: syn::ItemEnum) -> TokenStream {
             fn count_unresolved(&self) -> u32 {
                 #map_count_tokens 0
             }
+
+            fn visit(&self, field: &str, f: &mut dyn FnMut(&str, crate::character::StagedView)) {
+                #map_visit_tokens
+            }
         }
 
         impl Serialize for #enum_ident {
@@ -242,16 +256,27 @@ pub(crate) fn dynamic_choose(ast: syn::ItemTrait) -> TokenStream {
     let lower_ident = format_ident!("{}", ident.to_string().to_snake_case());
     let get_all_ident = format_ident!("get_all_{}", lower_ident);
     let default_ident = format_ident!("default_{}", lower_ident);
+    let deserialize_ident = format_ident!("deserialize_{}", lower_ident);
     (quote! {
-        #[typetag::serde]
+        #[typetag::serialize]
         #ast
 
+        impl<'de> serde::Deserialize<'de> for Box<dyn #ident> {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: serde::Deserializer<'de> {
+                let (name, value) = crate::unknown::split_tag(serde::Deserialize::deserialize(deserializer)?)?;
+                crate::content::#deserialize_ident(name, value).map_err(serde::de::Error::custom)
+            }
+        }
+
         impl Chooseable for Box<dyn #ident> {
-            fn choose(&mut self, choice: &str, index: usize) {
+            fn choose(&mut self, choice: &str, index: usize) -> Result<(), crate::ui::ChoiceError> {
                 if index == 0 {
-                    *self = crate::content::#lower_ident(choice).expect(&format!("choice not found: {:?}", choice));
+                    *self = crate::content::#lower_ident(choice)
+                        .ok_or_else(|| crate::ui::ChoiceError::NotFound(choice.to_string()))?;
+                    Ok(())
                 } else {
-                    panic!("index must be 0 for dynamic single choice, was {}", index)
+                    Err(crate::ui::ChoiceError::IndexOutOfBounds { index, len: 1 })
                 }
             }
             fn to_serial(&self) -> crate::ui::ChoiceSerial {
@@ -263,11 +288,13 @@ pub(crate) fn dynamic_choose(ast: syn::ItemTrait) -> TokenStream {
         }
 
         impl<const N: usize> Chooseable for [Box<dyn #ident>; N] {
-            fn choose(&mut self, choice: &str, index: usize) {
+            fn choose(&mut self, choice: &str, index: usize) -> Result<(), crate::ui::ChoiceError> {
                 if index < N {
-                    self[index] = crate::content::#lower_ident(choice).expect(&format!("choice not found: {}", choice));
+                    self[index] = crate::content::#lower_ident(choice)
+                        .ok_or_else(|| crate::ui::ChoiceError::NotFound(choice.to_string()))?;
+                    Ok(())
                 } else {
-                    panic!("index must be less than {}, was {}", N, index)
+                    Err(crate::ui::ChoiceError::IndexOutOfBounds { index, len: N })
                 }
             }
             fn to_serial(&self) -> crate::ui::ChoiceSerial {
@@ -278,11 +305,13 @@ pub(crate) fn dynamic_choose(ast: syn::ItemTrait) -> TokenStream {
         }
 
         impl Chooseable for Vec<Box<dyn #ident>> {
-            fn choose(&mut self, choice: &str, index: usize) {
+            fn choose(&mut self, choice: &str, index: usize) -> Result<(), crate::ui::ChoiceError> {
                 if index < self.len() {
-                    self[index] = crate::content::#lower_ident(choice).expect(&format!("choice not found: {}", choice));
+                    self[index] = crate::content::#lower_ident(choice)
+                        .ok_or_else(|| crate::ui::ChoiceError::NotFound(choice.to_string()))?;
+                    Ok(())
                 } else {
-                    panic!("index must be less than {}, was {}", self.len(), index)
+                    Err(crate::ui::ChoiceError::IndexOutOfBounds { index, len: self.len() })
                 }
             }
             fn to_serial(&self) -> crate::ui::ChoiceSerial {
@@ -6,21 +6,37 @@ use syn::parse::Parser;
 use std::sync::atomic::{AtomicU64, Ordering};
 use syn::Type::Verbatim;
 use std::iter::FromIterator;
+use inflector::Inflector;
 
-pub(crate) fn content(ast: syn::ItemImpl) -> TokenStream {
+pub(crate) fn content(mut ast: syn::ItemImpl) -> TokenStream {
     let clone = ast.clone();
     let ty = match clone.trait_ {
         Some((_, p, _)) => p,
         None => panic!("must be impl for something")
     };
+    let kind = ty.segments.last()
+        .expect("trait path has no segments?")
+        .ident.to_string().to_sentence_case().to_lowercase();
+    for item in &mut ast.items {
+        if let syn::ImplItem::Method(method) = item {
+            if method.sig.ident == "resolve" {
+                method.block.stmts.insert(0, syn::parse_quote! {
+                    let _content = crate::character::enter_content(#kind, DNDCENT_NAME);
+                });
+            }
+        }
+    }
     let name = clone.self_ty;
     (quote! {
         pub fn new() -> Box<dyn #ty> {
-            Box::new( #name {
-                ..Default::default()
-            } )
+            Box::new(<#name as Default>::default())
         }
-        #[typetag::serde]
+        /// The name this content is saved under.
+        pub const TYPETAG_NAME: &str = stringify!(#name);
+        pub fn deserialize(value: serde_json::Value) -> serde_json::Result<Box<dyn #ty>> {
+            Ok(Box::new(serde_json::from_value::<#name>(value)?))
+        }
+        #[typetag::serialize]
         #ast
     }).into()
 }
@@ -36,6 +52,7 @@ pub(crate) fn stages(input: TokenStream, stage: &'static str) -> TokenStream2 {
 
     let request_stage = format_ident!("request_{}", stage);
     let confirm_stage = format_ident!("confirm_{}", stage);
+    let kind = format_ident!("{}", stage.to_pascal_case());
     let mut acc: TokenStream2 = quote! {};
     for seg in ast {
         let id = next_id();
@@ -53,7 +70,7 @@ pub(crate) fn stages(input: TokenStream, stage: &'static str) -> TokenStream2 {
             }
             None => (quote! { #id }, quote! {})
         };
-        match seg {
+        let (left, mut right, write) = match seg {
             syn::Expr::Assign(
                 syn::ExprAssign {
                     left,
@@ -61,20 +78,10 @@ pub(crate) fn stages(input: TokenStream, stage: &'static str) -> TokenStream2 {
                     ..
                 }
             ) => {
-                let expanded_right = expand_carriers(right.to_token_stream());
-                acc = quote! {
-                    #acc
-                    #make_hash
-                    if (#left).#request_stage(#id_expr) {
-                        match (|| -> Result<_, ()> {Ok(#expanded_right)})() {
-                            Ok(v) => {
-                                *#left = v;
-                                (#left).#confirm_stage(#id_expr);
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+                let write = quote! {
+                    *#left = v;
+                };
+                (left, right, write)
             },
             syn::Expr::AssignOp(
                 syn::ExprAssignOp {
@@ -91,21 +98,11 @@ pub(crate) fn stages(input: TokenStream, stage: &'static str) -> TokenStream2 {
                     syn::BinOp::ShrEq(..) => "extend",
                     _ => ""
                 });
-                let expanded_right = expand_carriers(right.to_token_stream());
-                acc = quote! {
-                    #acc
-                    #make_hash
-                    if (#left).#request_stage(#id_expr) {
-                        match (|| -> Result<_, ()> {Ok(#expanded_right)})() {
-                            Ok(v) => {
-                                use std::ops::{AddAssign, SubAssign};
-                                (*#left).#func(v);
-                                (#left).#confirm_stage(#id_expr);
-                            }
-                            _ => {}
-                        }
-                    }
+                let write = quote! {
+                    use std::ops::{AddAssign, SubAssign};
+                    (*#left).#func(v);
                 };
+                (left, right, write)
             },
             expr => {
                 tag = Some(id);
@@ -114,9 +111,35 @@ pub(crate) fn stages(input: TokenStream, stage: &'static str) -> TokenStream2 {
                     #acc
                     let mut #hasher_ident = std::collections::hash_map::DefaultHasher::new();
                     (#expr).hash(&mut #hasher_ident);
+                };
+                continue;
+            }
+        };
+        connect_elements(&mut right);
+        let info_ident = format_ident!("DNDCENT_STAGE_INFO_{}", id);
+        let writes = pretty_tokens(left.to_token_stream());
+        let carriers = collect_carriers(&right);
+        let reads = carriers.iter().map(|carrier| pretty_tokens(carrier.to_token_stream()));
+        let expanded_right = expand_carriers(right.to_token_stream());
+        acc = quote! {
+            #acc
+            #make_hash
+            static #info_ident: crate::character::StageInfo = crate::character::StageInfo {
+                id: #id,
+                kind: crate::character::StageKind::#kind,
+                writes: #writes,
+                reads: &[ #(#reads),* ],
+                location: concat!(file!(), ":", line!())
+            };
+            let reads = [ #((#carriers).address()),* ];
+            if (#left).#request_stage(#id_expr, &#info_ident, &reads) {
+                if let Ok(v) = (|| -> Result<_, ()> { let v = #expanded_right; Ok(v) })() {
+                    let before = (#left).snapshot();
+                    #write
+                    (#left).#confirm_stage(#id_expr, before);
                 }
             }
-        }
+        };
     }
     quote! {
         {
@@ -126,6 +149,133 @@ pub(crate) fn stages(input: TokenStream, stage: &'static str) -> TokenStream2 {
     }
 }
 
+/// Finds the expressions that `?` carriers are applied to, like `c.abilities.dexterity`.
+///
+/// These are evaluated outside the stage to find out which fields it reads, so they can only
+/// use variables from outside the macro.
+fn collect_carriers(expr: &syn::Expr) -> Vec<syn::Expr> {
+    use syn::visit::Visit;
+
+    struct Carriers(Vec<syn::Expr>);
+    impl<'ast> Visit<'ast> for Carriers {
+        fn visit_expr_try(&mut self, node: &'ast syn::ExprTry) {
+            self.0.push((*node.expr).clone());
+            syn::visit::visit_expr_try(self, node);
+        }
+    }
+
+    let mut carriers = Carriers(vec! []);
+    carriers.visit_expr(expr);
+    carriers.0
+}
+
+/// Connects every `Element::Choice`, `Element::Toggle` and `Element::Resource` built in a stage
+/// to the field its `data` points to, without keeping a reference to it.
+///
+/// The element gets an `id` from the content that's resolving and the field, unless it already
+/// has one, and the `data` reference is handed to `crate::ui::choice_data`,
+/// `crate::ui::toggle_data` or `crate::ui::resource_data`, which make a pending edit to the field
+/// if it is for this element.
+///
+/// Also looks inside `vec!`, since elements are often pushed a few at a time.
+fn connect_elements(expr: &mut syn::Expr) {
+    use syn::visit_mut::VisitMut;
+
+    struct Ids;
+    impl VisitMut for Ids {
+        fn visit_expr_mut(&mut self, node: &mut syn::Expr) {
+            syn::visit_mut::visit_expr_mut(self, node);
+            if let syn::Expr::Struct(element) = node {
+                if let Some(connected) = connect_element(element) {
+                    *node = connected;
+                }
+            }
+        }
+
+        fn visit_macro_mut(&mut self, node: &mut syn::Macro) {
+            if !node.path.is_ident("vec") {
+                return;
+            }
+            if let Ok(mut elements) = syn::punctuated::Punctuated::<syn::Expr, Token![,]>::parse_terminated
+                .parse2(node.tokens.clone()) {
+                for element in &mut elements {
+                    self.visit_expr_mut(element);
+                }
+                node.tokens = elements.to_token_stream();
+            }
+        }
+    }
+
+    Ids.visit_expr_mut(expr);
+}
+
+fn connect_element(element: &syn::ExprStruct) -> Option<syn::Expr> {
+    let segments: Vec<String> = element.path.segments.iter().map(|s| s.ident.to_string()).collect();
+    let variant = match &segments[..] {
+        [.., e, v] if e == "Element" && (v == "Choice" || v == "Toggle" || v == "Resource") => v.clone(),
+        _ => return None
+    };
+    let field = |name: &str| element.fields.iter()
+        .find(|f| matches!(&f.member, syn::Member::Named(i) if i == name))
+        .map(|f| f.expr.clone());
+    let data = field("data")?;
+    let id = field("id").unwrap_or_else(|| {
+        let name = match &data {
+            syn::Expr::Reference(r) => pretty_tokens(r.expr.to_token_stream()),
+            e => pretty_tokens(e.to_token_stream())
+        };
+        let name = name.strip_prefix("self.").unwrap_or(&name).to_string();
+        syn::parse_quote! { crate::character::element_id(#name) }
+    });
+
+    let mut connected = element.clone();
+    let mut locals = quote! {};
+    connected.fields = element.fields.iter()
+        .filter(|f| !matches!(&f.member, syn::Member::Named(i) if i == "data" || i == "id"))
+        .cloned()
+        .collect();
+    match &*variant {
+        "Toggle" => connected.fields.push(syn::parse_quote! {
+            id: {
+                crate::ui::toggle_data(&dndcent_element_id, #data);
+                dndcent_element_id
+            }
+        }),
+        "Resource" => {
+            // The max and recharge go in the element too, so they're only evaluated once.
+            for field in connected.fields.iter_mut() {
+                if let syn::Member::Named(name) = &field.member {
+                    if name == "max" || name == "recharge" {
+                        let local = format_ident!("dndcent_resource_{}", name);
+                        let expr = &field.expr;
+                        locals = quote! { #locals let #local = #expr; };
+                        field.expr = syn::parse_quote! { #local };
+                    }
+                }
+            }
+            connected.fields.push(syn::parse_quote! {
+                data: crate::ui::resource_data(&dndcent_element_id, #data, dndcent_resource_max, dndcent_resource_recharge)
+            });
+            connected.fields.push(syn::parse_quote! { id: dndcent_element_id });
+        }
+        _ => {
+            connected.fields.push(syn::parse_quote! { data: crate::ui::choice_data(&dndcent_element_id, #data) });
+            connected.fields.push(syn::parse_quote! { id: dndcent_element_id });
+        }
+    }
+    Some(syn::parse_quote! {
+        {
+            let dndcent_element_id: String = #id;
+            #locals
+            #connected
+        }
+    })
+}
+
+fn pretty_tokens(stream: TokenStream2) -> String {
+    stream.to_string().chars().filter(|c| !c.is_whitespace()).collect()
+}
+
 static ID: AtomicU64 = AtomicU64::new(0);
 pub fn next_id() -> u64 {
     ID.fetch_add(1, Ordering::SeqCst)
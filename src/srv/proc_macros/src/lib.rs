@@ -94,6 +94,10 @@ pub fn dynamic_choose(_: TokenStream, input: TokenStream) -> TokenStream {
 ///
 /// finalize unwraps all the Staged-wrapped and Map<Staged>-wrapped values in Character, so
 /// it can be serialized without the unnecessary Staged structs.
+///
+/// visit_stages walks every Staged value in Character by field name, which is how resolution
+/// orders the stages, and how the deadlock report and the dependency graph find out who wrote
+/// and read what.
 #[proc_macro_derive(FinalizeCharacter)]
 pub fn derive_finalize(input: TokenStream) -> TokenStream {
     let ast: syn::DeriveInput = syn::parse(input).expect("expected derive input");
@@ -10,16 +10,32 @@ pub(crate) fn registry() -> TokenStream {
     let mut registry_statics = quote! {};
     let mut description_search = quote! {};
     let mut registration_dump = quote! {};
+    let mut placeholders = vec! [];
     for (key, entries) in &registration {
         let type_string_upper = key.to_pascal_case();
         let type_ident_upper = format_ident!("{}", type_string_upper);
         let type_string_lower = key.to_string();
         let type_ident_lower= format_ident!("{}", type_string_lower);
         let static_ident = format_ident!("{}", key.to_uppercase());
+        placeholders.push(format!("Unknown{}", type_string_upper));
 
         let get_all_ident = format_ident!("get_all_{}", type_ident_lower);
         let default_ident = format_ident!("default_{}", type_ident_lower);
         let unknown_ident = format_ident!("unknown_{}", type_ident_lower);
+        let deserialize_ident = format_ident!("deserialize_{}", type_ident_lower);
+        let placeholder = format!("Unknown{}", type_string_upper);
+        let mut known = quote! {};
+        for (collection, source, content) in entries {
+            let collection_ident = format_ident!("{}", collection);
+            let source_ident = format_ident!("{}", source);
+            let content_ident = format_ident!("{}", content);
+            known = quote! {
+                #known
+                if name == #collection_ident::#source_ident::#type_ident_lower::#content_ident::TYPETAG_NAME {
+                    return #collection_ident::#source_ident::#type_ident_lower::#content_ident::deserialize(value);
+                }
+            };
+        }
         content_functions = quote! {
             #content_functions
             pub fn #type_ident_lower(search_name: &str) -> Option<Box<dyn #type_ident_upper>> {
@@ -31,13 +47,22 @@ pub(crate) fn registry() -> TokenStream {
                 None
             }
             pub fn #get_all_ident() -> Vec<&'static str> {
-                #static_ident.keys().map(
+                let mut all: Vec<&'static str> = #static_ident.keys().map(
                     |reg| reg.name
-                ).collect()
+                ).collect();
+                all.sort_unstable();
+                all
             }
             pub fn #default_ident() -> Box<dyn #type_ident_upper> {
                 system::defaults::#type_ident_lower::#unknown_ident::new()
             }
+            /// Deserializes the content saved as `{name: value}`. Content this build doesn't
+            /// have is kept in a placeholder.
+            pub(crate) fn #deserialize_ident(name: String, value: serde_json::Value) -> serde_json::Result<Box<dyn #type_ident_upper>> {
+                #known
+                crate::unknown::replaced(&name, #placeholder);
+                system::defaults::#type_ident_lower::#unknown_ident::deserialize(crate::unknown::tagged(name, value))
+            }
         };
 
         let mut registry_static_entries = quote! {};
@@ -91,6 +116,9 @@ pub(crate) fn registry() -> TokenStream {
             #description_search
             panic!("could not find registration")
         }
+        /// Typetag names of the `system::defaults` placeholders, one per kind of content.
+        pub const PLACEHOLDERS: &[&str] = &[ #(#placeholders),* ];
+
         pub fn get_registrations() -> Vec<&'static Registration<'static>> {
             let mut result: Vec<&'static Registration<'static>> = vec! [];
             #registration_dump
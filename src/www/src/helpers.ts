@@ -2,6 +2,13 @@ export function signedInt(i: number): string {
     return `${i>=0?'+':''}${i}`;
 }
 
+export function breakdown(b: any, field: string): string {
+    if (b[field] === undefined) {
+        return '';
+    }
+    return b[field].map((contribution: any) => contribution.text).join('\n');
+}
+
 // @ts-ignore
 import "/scripts/remarkable.min.js";
 // @ts-ignore
@@ -1,22 +1,140 @@
-import {Writable, writable} from 'svelte/store';
+import {Writable, writable, get} from 'svelte/store';
 import type {Registration} from './helpers';
 
 export let c: Writable<any> = writable({error: "haven't fetched character yet."});
+// Where the numbers in `c` came from. It's big, so it's fetched separately, after `c` changes.
+export let b: Writable<any> = writable({});
 updateCharacter();
 
+// The server can serve several characters. The one shown is picked by the url hash,
+// like `#jeebus-creebus`, or is the first one the server has.
+let characterId: string | null = null;
+
+window.addEventListener('hashchange', () => {
+    characterId = null;
+    updateCharacter();
+    listen();
+});
+
+// Edits made in other tabs (or by other people) arrive through the server's event stream.
+let events: EventSource | null = null;
+listen();
+
+async function listen() {
+    const id = await currentCharacter();
+    if (events !== null) {
+        events.close();
+    }
+    const location = window.location.hostname;
+    const port = window.location.port;
+    events = new EventSource(`http://${location}:${port}/character/${encodeURIComponent(id)}/events`);
+    events.addEventListener('character', (e: MessageEvent) => {
+        c.set(JSON.parse(e.data));
+        updateBreakdown();
+        window.dispatchEvent(new CustomEvent('repack'));
+    });
+    events.addEventListener('patch', (e: MessageEvent) => {
+        c.update((doc) => applyPatch(doc, JSON.parse(e.data)));
+        updateBreakdown();
+        window.dispatchEvent(new CustomEvent('repack'));
+    });
+}
+
+// Applies the add, remove and replace operations of a JSON patch, which is all the server sends.
+function applyPatch(doc: any, patch: any[]): any {
+    for (const op of patch) {
+        if (op.path === '') {
+            doc = op.value;
+            continue;
+        }
+        const keys = op.path.substring(1).split('/')
+            .map((k: string) => k.replace(/~1/g, '/').replace(/~0/g, '~'));
+        const last = keys.pop();
+        let parent = doc;
+        for (const k of keys) {
+            parent = parent[k];
+        }
+        if (Array.isArray(parent) && op.op === 'add') {
+            parent.splice(Number(last), 0, op.value);
+        } else if (Array.isArray(parent) && op.op === 'remove') {
+            parent.splice(Number(last), 1);
+        } else if (op.op === 'remove') {
+            delete parent[last];
+        } else {
+            parent[last] = op.value;
+        }
+    }
+    return doc;
+}
+
+async function currentCharacter(): Promise<string> {
+    if (characterId === null) {
+        const hash = decodeURIComponent(window.location.hash.substring(1));
+        if (hash !== '') {
+            characterId = hash;
+        } else {
+            const characters = await listCharacters();
+            characterId = characters.length > 0 ? characters[0].id : '';
+        }
+    }
+    return characterId;
+}
+
+async function sendCharacterRequest(path: string, data: any) {
+    const id = await currentCharacter();
+    return sendRequest(`/character/${encodeURIComponent(id)}${path}`, data);
+}
+
+export async function listCharacters() {
+    return sendRequest('/characters', null);
+}
+
 async function getCharacter() {
-    return sendRequest('', null);
+    return sendCharacterRequest('', null);
 }
 
 export function updateCharacter() {
     getCharacter().then((value) => c.set(value));
+    updateBreakdown();
+}
+
+function updateBreakdown() {
+    sendCharacterRequest('/breakdown', null).then((value) => {
+        if (!('error' in value)) {
+            b.set(value);
+        }
+    });
 }
 
+// Successful edits, undos and redos come back as a patch, but so does the event stream, which is
+// what updates `c`.
+//
+// Every change says which revision of the character it was made against. If someone else changed
+// it first, the server turns it down with a `stale_revision` error, and the event stream brings
+// the page up to date.
 export async function editCharacter(request: any) {
+    request.revision = get(c).revision;
     console.log(request);
-    sendRequest('/edit', request).then((value) => {
-        c.set(value)
-        window.dispatchEvent(new CustomEvent('repack'));
+    sendCharacterRequest('/edit', request).then((value) => {
+        if ('error' in value) {
+            console.error(value);
+        }
+    });
+}
+
+export async function undo() {
+    historyRequest('/undo');
+}
+
+export async function redo() {
+    historyRequest('/redo');
+}
+
+async function historyRequest(path: string) {
+    sendCharacterRequest(`${path}?revision=${get(c).revision}`, null).then((value) => {
+        if ('error' in value) {
+            console.error(value);
+        }
     });
 }
 